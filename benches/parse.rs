@@ -0,0 +1,35 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use fandom_data::scrape::search_page_to_works;
+
+const SEARCH_PAGE: &str = include_str!("../src/search.html");
+const WORK_LIST_START: &str = r#"<ol class="work index group">"#;
+const WORK_LIST_END: &str = "</ol>";
+
+/// Build a search page with the works of the fixture repeated `copies` times.
+fn repeated_search_page(copies: usize) -> String {
+    let start = SEARCH_PAGE.find(WORK_LIST_START).expect("work list start") + WORK_LIST_START.len();
+    let end = start
+        + SEARCH_PAGE[start..]
+            .find(WORK_LIST_END)
+            .expect("work list end");
+    format!(
+        "{}{}{}",
+        &SEARCH_PAGE[..start],
+        SEARCH_PAGE[start..end].repeat(copies),
+        &SEARCH_PAGE[end..]
+    )
+}
+
+fn bench_search_page_to_works(c: &mut Criterion) {
+    let large_page = repeated_search_page(5);
+    let mut group = c.benchmark_group("search_page_to_works");
+    for (name, page) in [("fixture", SEARCH_PAGE), ("large", large_page.as_str())].iter() {
+        let works = search_page_to_works(page).unwrap().len();
+        group.throughput(Throughput::Elements(works as u64));
+        group.bench_function(*name, |b| b.iter(|| search_page_to_works(page).unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_search_page_to_works);
+criterion_main!(benches);
@@ -0,0 +1,172 @@
+use anyhow::{anyhow, Context, Result};
+use arrow::{
+    array::{ArrayRef, Date32Builder, ListBuilder, StringBuilder, UInt32Builder},
+    datatypes::{DataType, DateUnit, Field, Schema},
+    record_batch::RecordBatch,
+};
+use chrono::Datelike;
+use fandom_data::{format::Format, scrape::Work};
+use itertools::Itertools;
+use parquet::arrow::ArrowWriter;
+use std::{
+    fs::File,
+    io::{self, BufRead},
+    path::PathBuf,
+    sync::Arc,
+};
+use structopt::StructOpt;
+
+/// Days between 0001-01-01 and the unix epoch, which Arrow dates count from.
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "convert", about = "Convert ao3 data to parquet")]
+struct Opt {
+    /// Works data to convert, as jsonl or CSV by extension, gunzipped if the extension is `.gz`
+    #[structopt(long = "input")]
+    input: PathBuf,
+
+    /// Parquet file to write, with a `.parquet` extension
+    #[structopt(long = "output")]
+    output: PathBuf,
+
+    /// Number of works to write in one row group
+    #[structopt(long = "chunk-size", default_value = "1024")]
+    chunk_size: usize,
+}
+
+fn tags_field(name: &str) -> Field {
+    Field::new(
+        name,
+        DataType::List(Box::new(Field::new("item", DataType::Utf8, true))),
+        false,
+    )
+}
+
+fn works_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("title", DataType::Utf8, false),
+        tags_field("authors"),
+        tags_field("relationships"),
+        tags_field("characters"),
+        tags_field("freeforms"),
+        Field::new("date", DataType::Date32(DateUnit::Day), false),
+        Field::new("updated", DataType::Date32(DateUnit::Day), true),
+        Field::new("language", DataType::Utf8, false),
+        Field::new("words", DataType::UInt32, false),
+        Field::new("chapters", DataType::UInt32, false),
+        Field::new("kudos", DataType::UInt32, false),
+        Field::new("hits", DataType::UInt32, false),
+        Field::new("comments", DataType::UInt32, false),
+        Field::new("bookmarks", DataType::UInt32, false),
+    ])
+}
+
+fn append_tags(builder: &mut ListBuilder<StringBuilder>, tags: &[String]) -> Result<()> {
+    for tag in tags.iter() {
+        builder.values().append_value(tag)?;
+    }
+    builder.append(true)?;
+    Ok(())
+}
+
+fn works_to_batch(schema: &Arc<Schema>, works: &[Work]) -> Result<RecordBatch> {
+    let mut ids = StringBuilder::new(works.len());
+    let mut titles = StringBuilder::new(works.len());
+    let mut authors = ListBuilder::new(StringBuilder::new(works.len()));
+    let mut relationships = ListBuilder::new(StringBuilder::new(works.len()));
+    let mut characters = ListBuilder::new(StringBuilder::new(works.len()));
+    let mut freeforms = ListBuilder::new(StringBuilder::new(works.len()));
+    let mut dates = Date32Builder::new(works.len());
+    let mut updated = Date32Builder::new(works.len());
+    let mut languages = StringBuilder::new(works.len());
+    let mut words = UInt32Builder::new(works.len());
+    let mut chapters = UInt32Builder::new(works.len());
+    let mut kudos = UInt32Builder::new(works.len());
+    let mut hits = UInt32Builder::new(works.len());
+    let mut comments = UInt32Builder::new(works.len());
+    let mut bookmarks = UInt32Builder::new(works.len());
+
+    for work in works.iter() {
+        ids.append_value(&work.id)?;
+        titles.append_value(&work.title)?;
+        append_tags(&mut authors, &work.authors)?;
+        append_tags(&mut relationships, &work.relationships)?;
+        append_tags(&mut characters, &work.characters)?;
+        append_tags(&mut freeforms, &work.freeforms)?;
+        dates.append_value(work.date.num_days_from_ce() - UNIX_EPOCH_DAYS_FROM_CE)?;
+        updated.append_option(
+            work.updated
+                .map(|date| date.num_days_from_ce() - UNIX_EPOCH_DAYS_FROM_CE),
+        )?;
+        languages.append_value(&work.language)?;
+        words.append_value(work.words)?;
+        chapters.append_value(work.chapters)?;
+        kudos.append_value(work.kudos)?;
+        hits.append_value(work.hits)?;
+        comments.append_value(work.comments)?;
+        bookmarks.append_value(work.bookmarks)?;
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(ids.finish()),
+        Arc::new(titles.finish()),
+        Arc::new(authors.finish()),
+        Arc::new(relationships.finish()),
+        Arc::new(characters.finish()),
+        Arc::new(freeforms.finish()),
+        Arc::new(dates.finish()),
+        Arc::new(updated.finish()),
+        Arc::new(languages.finish()),
+        Arc::new(words.finish()),
+        Arc::new(chapters.finish()),
+        Arc::new(kudos.finish()),
+        Arc::new(hits.finish()),
+        Arc::new(comments.finish()),
+        Arc::new(bookmarks.finish()),
+    ];
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn line_to_work(line: io::Result<String>) -> Result<Work> {
+    serde_json::from_str(&line.context("input line")?).context("line json")
+}
+
+fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let opt = Opt::from_args();
+
+    if Format::from_path(&opt.output)? != Format::Parquet {
+        return Err(anyhow!("Output {:?} must be parquet", opt.output));
+    }
+    let input_format = Format::from_path(&opt.input)?;
+    let file = input_format.open(&opt.input).context("input file")?;
+    let works: Box<dyn Iterator<Item = Result<Work>>> = match input_format {
+        Format::Jsonl | Format::JsonlGz => Box::new(file.lines().map(line_to_work)),
+        Format::Csv | Format::CsvGz => {
+            Box::new(Work::read_csv(file).map(|work| work.context("input row")))
+        }
+        Format::Parquet => return Err(anyhow!("Input is already parquet")),
+    };
+
+    let schema = Arc::new(works_schema());
+    let mut writer = ArrowWriter::try_new(
+        File::create(&opt.output).context("output file")?,
+        schema.clone(),
+        None,
+    )?;
+
+    for (chunk_index, works) in works.chunks(opt.chunk_size).into_iter().enumerate() {
+        log::info!(
+            "Processing chunk {} ({} documents)",
+            chunk_index,
+            (chunk_index + 1) * opt.chunk_size
+        );
+        let works = works.collect::<Result<Vec<Work>>>()?;
+        writer.write(&works_to_batch(&schema, &works)?)?;
+    }
+
+    writer.close()?;
+    Ok(())
+}
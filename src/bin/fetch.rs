@@ -1,81 +1,571 @@
-use anyhow::Result;
-use fandom_data::scrape::{page_url, search_page_to_works, ENDPOINT_AO3};
+use anyhow::{anyhow, Context, Error, Result};
+use chrono::NaiveDate;
+use fandom_data::{
+    format::{Format, Output},
+    scrape::{
+        bookmarks_page_to_works, bookmarks_url, fetch_page_with_retry, login, search_page_info,
+        search_page_tag_hrefs, search_page_to_works_lenient, SearchOpt, SortColumn, SortDirection,
+        Work, ENDPOINT_AO3,
+    },
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::Rng;
 use rayon::prelude::*;
-use reqwest::{blocking::Client, Url};
-use std::io::{self, Write};
-use std::{thread::sleep, time::Duration};
+use reqwest::blocking::Client;
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "fetch", about = "Fetch ao3 data")]
 struct Opt {
-    /// Page to start fetching from
-    #[structopt(long = "start", default_value = "1")]
-    start: u32,
+    #[structopt(flatten)]
+    search: SearchOpt,
 
-    /// Number of pages to fetch at most
-    #[structopt(long = "count", default_value = "1")]
-    count: u32,
+    /// Pages to fetch, such as `1-5,10,20-22`, overriding `--start` and `--count`
+    #[structopt(long = "pages")]
+    pages: Option<PageList>,
 
-    /// Interval between requests in seconds, to avoid rate limiting
-    #[structopt(long = "interval")]
-    interval: Option<u64>,
+    /// Maximum requests per second across all threads
+    #[structopt(long = "rate")]
+    rate: Option<f64>,
+
+    /// Maximum random delay in seconds to add to each interval, so requests are less regular
+    #[structopt(long = "jitter", default_value = "0")]
+    jitter: f64,
 
     /// Number of requests to process in parallel
     #[structopt(short = "n", long = "threads", default_value = "1")]
     threads: usize,
+
+    /// Pages to fetch works from: works, to search works, or bookmarks, to list a user's bookmarks
+    #[structopt(long = "mode", default_value = "works")]
+    mode: Mode,
+
+    /// User whose bookmarks to fetch, in bookmarks mode
+    #[structopt(long = "bookmarks-of", required_if("mode", "bookmarks"))]
+    bookmarks_of: Option<String>,
+
+    /// Sort descending, so the newest works come first with the default `--sort`, overriding `--order`
+    #[structopt(long = "newest-first")]
+    newest_first: bool,
+
+    /// Output format: jsonl or csv. Defaults to the format of `--output` by extension, or jsonl
+    #[structopt(long = "format")]
+    format: Option<OutputFormat>,
+
+    /// File to write works to instead of stdout, as jsonl or CSV by extension, gzipped if the
+    /// extension is `.gz`
+    #[structopt(long = "output")]
+    output: Option<PathBuf>,
+
+    /// Include a link to each tag, in the `tag_hrefs` field of the jsonl output, in works mode
+    #[structopt(long = "tag-hrefs")]
+    tag_hrefs: bool,
+
+    /// Only write works with at least this many kudos
+    #[structopt(long = "min-kudos")]
+    min_kudos: Option<u32>,
+
+    /// Stop once this many works have been written, across all pages
+    #[structopt(long = "max-works")]
+    max_works: Option<usize>,
+
+    /// Number of times to retry a page after a connection error or server error
+    #[structopt(long = "max-retries", default_value = "3")]
+    max_retries: u32,
+
+    /// Don't show a progress bar of the pages fetched, which is also hidden if stdout is not a
+    /// terminal
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Log in to the Archive as this user, to include works restricted to registered users
+    #[structopt(long = "username", env = "AO3_USERNAME", requires = "password")]
+    username: Option<String>,
+
+    /// Password to log in to the Archive with
+    #[structopt(long = "password", env = "AO3_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
 }
 
-fn main() -> Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Mode {
+    Works,
+    Bookmarks,
+}
+
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "works" => Ok(Self::Works),
+            "bookmarks" => Ok(Self::Bookmarks),
+            _ => Err(anyhow!("Invalid mode: '{}'", string)),
+        }
+    }
+}
+
+/// Page numbers to fetch, in ascending order without duplicates.
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct PageList(Vec<u32>);
+
+impl FromStr for PageList {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid pages: '{}'", string);
+        let mut pages = BTreeSet::new();
+        for part in string.split(',').map(str::trim) {
+            let (first, last) = match part.split_once('-') {
+                Some((first, last)) => (first.trim().parse(), last.trim().parse()),
+                None => (part.parse(), part.parse()),
+            };
+            let (first, last): (u32, u32) =
+                (first.map_err(|_| invalid())?, last.map_err(|_| invalid())?);
+            if first == 0 || first > last {
+                return Err(invalid());
+            }
+            pages.extend(first..=last);
+        }
+        Ok(Self(pages.into_iter().collect()))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum OutputFormat {
+    Jsonl,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "jsonl" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            _ => Err(anyhow!("Invalid output format: '{}'", string)),
+        }
+    }
+}
+
+/// Choose the output format from `--format`, or else from the output file's format.
+fn output_format(
+    format: Option<&OutputFormat>,
+    file_format: Option<Format>,
+) -> Result<OutputFormat> {
+    let from_file = match file_format {
+        Some(file_format) if file_format.is_jsonl() => Some(OutputFormat::Jsonl),
+        Some(file_format) if file_format.is_csv() => Some(OutputFormat::Csv),
+        Some(file_format) => return Err(anyhow!("Can't write fetched works as {:?}", file_format)),
+        None => None,
+    };
+    match (format, from_file) {
+        (Some(format), Some(from_file)) if *format != from_file => Err(anyhow!(
+            "Output format {:?} does not match the output file extension",
+            format
+        )),
+        (Some(format), _) => Ok(format.clone()),
+        (None, from_file) => Ok(from_file.unwrap_or(OutputFormat::Jsonl)),
+    }
+}
+
+/// Totals of the works written, to sanity check what a fetch covered.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct FetchSummary {
+    works: usize,
+    pages: usize,
+    earliest: Option<NaiveDate>,
+    latest: Option<NaiveDate>,
+    words: u64,
+}
+
+impl FetchSummary {
+    fn add_page(&mut self, works: &[Work]) {
+        if works.is_empty() {
+            return;
+        }
+        self.pages += 1;
+        self.works += works.len();
+        for work in works.iter() {
+            self.earliest = Some(self.earliest.map_or(work.date, |date| date.min(work.date)));
+            self.latest = Some(self.latest.map_or(work.date, |date| date.max(work.date)));
+            self.words += u64::from(work.words);
+        }
+    }
+}
+
+impl fmt::Display for FetchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Fetched {} works from {} pages", self.works, self.pages)?;
+        if let (Some(earliest), Some(latest)) = (self.earliest, self.latest) {
+            write!(f, ", dated {} to {}", earliest, latest)?;
+        }
+        write!(f, ", {} words in total", self.words)
+    }
+}
+
+/// Spaces requests evenly so that, across all threads, no more than `rate` start each second.
+#[derive(Debug)]
+struct RateLimiter {
+    period: Duration,
+    next: Mutex<Instant>,
+}
 
+impl RateLimiter {
+    fn new(rate: f64) -> Result<Self> {
+        if rate.is_nan() || rate <= 0. {
+            return Err(anyhow!("Invalid rate: '{}'", rate));
+        }
+        Ok(Self {
+            period: Duration::from_secs_f64(1. / rate),
+            next: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Reserve the next free slot, then block until it arrives.
+    fn wait(&self) {
+        let now = Instant::now();
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = (*next).max(now);
+            *next = slot + self.period;
+            slot
+        };
+        if slot > now {
+            sleep(slot - now);
+        }
+    }
+}
+
+/// Writes log lines above the progress bar, so they are not drawn over by it.
+struct ProgressLog(ProgressBar);
+
+impl Write for ProgressLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A hidden progress bar prints nothing, so logs go straight to stderr
+        if self.0.is_hidden() {
+            return io::stderr().write(buf);
+        }
+        self.0.println(String::from_utf8_lossy(buf).trim_end());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+fn main() -> Result<()> {
     let opt = Opt::from_args();
     rayon::ThreadPoolBuilder::new()
         .num_threads(opt.threads)
         .build_global()
         .unwrap();
 
-    let interval = opt.interval.map(Duration::from_secs);
-    let page_start = opt.start;
-    let page_count = opt.count;
-    let page_end = page_start + page_count;
-    let client = Client::new();
+    let order = if opt.newest_first {
+        SortDirection::Descending
+    } else {
+        opt.search.order.clone()
+    };
+    let pages = match &opt.pages {
+        Some(PageList(pages)) => pages.clone(),
+        None => (opt.search.start..opt.search.start + opt.search.count).collect(),
+    };
+    let page_start = pages.first().copied().unwrap_or(opt.search.start);
+
+    // Drawn to stderr apart from any works written to stdout, and hidden if stdout is
+    // redirected, such as when run from a script
+    let progress = if opt.quiet || !io::stdout().is_terminal() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(pages.len() as u64)
+    };
+    progress.set_style(
+        ProgressStyle::default_bar().template("{bar:40} {pos}/{len} pages, {eta} remaining"),
+    );
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .target(env_logger::Target::Pipe(Box::new(ProgressLog(
+            progress.clone(),
+        ))))
+        .init();
 
-    let stdout = io::stdout();
+    let file_format = opt.output.as_deref().map(Format::from_path).transpose()?;
+    let format = output_format(opt.format.as_ref(), file_format)?;
+    // Resuming from a later page only makes sense if pages are stable, which is
+    // only true when the oldest works come first
+    if page_start != 1
+        && (opt.search.sort != SortColumn::default() || order != SortDirection::default())
+    {
+        log::warn!(
+            "Resuming from page {} with a non-default sort, pages may have shifted since the last run",
+            page_start
+        );
+    }
 
-    (page_start..page_end)
+    let params = opt.search.page_url_params().sort_direction(order);
+
+    let interval = Duration::from_secs(opt.search.interval.unwrap_or(0));
+    let client = Client::builder()
+        .user_agent(&opt.search.user_agent)
+        .cookie_store(true)
+        .build()?;
+    if let (Some(username), Some(password)) = (&opt.username, &opt.password) {
+        log::info!("Logging in as {}", username);
+        login(&client, ENDPOINT_AO3, username, password)?;
+    }
+
+    let output = match (&opt.output, file_format) {
+        (Some(path), Some(file_format)) => file_format.create(path).context("output file")?,
+        _ => Output::stdout(),
+    };
+    let output = Mutex::new(output);
+    if format == OutputFormat::Csv {
+        let mut handle = output.lock().unwrap();
+        let mut writer = csv::Writer::from_writer(&mut *handle);
+        Work::write_csv_header(&mut writer)?;
+        writer.flush()?;
+    }
+
+    let summary = Mutex::new(FetchSummary::default());
+    let rate_limiter = opt.rate.map(RateLimiter::new).transpose()?;
+    let works_written = AtomicUsize::new(0);
+    let max_works_reached = || {
+        opt.max_works
+            .is_some_and(|max_works| works_written.load(Ordering::SeqCst) >= max_works)
+    };
+
+    pages
         .into_par_iter()
-        .map::<_, Result<(u32, Vec<_>)>>(|page_number| {
+        .map::<_, Result<(u32, usize, bool)>>(|page_number| {
+            // Pages already scheduled in parallel are skipped once the cap is reached
+            if max_works_reached() {
+                return Ok((page_number, 0, true));
+            }
             log::info!("Processing page {}", page_number);
-            let url = Url::parse(&page_url(ENDPOINT_AO3, page_number))?;
-            let html = &client.get(url).send()?.text()?;
-            let works = search_page_to_works(html)?;
-
-            let mut handle = stdout.lock();
-            for work in works.iter() {
-                handle.write_all(&serde_json::to_string(work)?.as_bytes())?;
-                handle.write_all(b"\n")?;
+            let url = match (&opt.mode, &opt.bookmarks_of) {
+                (Mode::Bookmarks, Some(user)) => bookmarks_url(ENDPOINT_AO3, user, page_number)?,
+                (Mode::Bookmarks, None) => return Err(anyhow!("--bookmarks-of is required")),
+                (Mode::Works, _) => params.build(ENDPOINT_AO3, page_number)?,
+            };
+            let html = &fetch_page_with_retry(&client, url, opt.max_retries, || {
+                if let Some(rate_limiter) = &rate_limiter {
+                    rate_limiter.wait();
+                }
+            })?;
+            let info = search_page_info(html);
+            if page_number == page_start {
+                if let Some(total_pages) = info.total_pages {
+                    log::info!("Search has {} pages of works", total_pages);
+                }
+            }
+            let (mut works, errors) = if info.no_results {
+                (Vec::new(), Vec::new())
+            } else if opt.mode == Mode::Bookmarks {
+                (bookmarks_page_to_works(html)?, Vec::new())
+            } else {
+                search_page_to_works_lenient(html)
+            };
+            for error in errors.iter() {
+                log::warn!("Skipping work on page {}: {}", page_number, error);
+            }
+            if opt.tag_hrefs {
+                let mut hrefs = search_page_tag_hrefs(html);
+                for work in works.iter_mut() {
+                    work.tag_hrefs = hrefs.remove(&work.id);
+                }
+            }
+            // Filtering may empty a page, which should not stop the fetch
+            let received = works.len();
+            if let Some(min_kudos) = opt.min_kudos {
+                works.retain(|work| work.kudos >= min_kudos);
+                log::info!(
+                    "Filtered {} works with fewer than {} kudos on page {}",
+                    received - works.len(),
+                    min_kudos,
+                    page_number
+                );
+            }
+
+            let mut handle = output.lock().unwrap();
+            // Count works while holding the output lock, so the cap is exact
+            if let Some(max_works) = opt.max_works {
+                let remaining = max_works.saturating_sub(works_written.load(Ordering::SeqCst));
+                works.truncate(remaining);
+            }
+            works_written.fetch_add(works.len(), Ordering::SeqCst);
+            summary.lock().unwrap().add_page(&works);
+            match format {
+                OutputFormat::Jsonl => {
+                    for work in works.iter() {
+                        serde_json::to_writer(&mut *handle, work)?;
+                        handle.write_all(b"\n")?;
+                    }
+                }
+                OutputFormat::Csv => {
+                    let mut writer = csv::Writer::from_writer(&mut *handle);
+                    for work in works.iter() {
+                        work.write_csv_record(&mut writer)?;
+                    }
+                    writer.flush()?;
+                }
             }
 
-            if let Some(interval) = interval {
-                sleep(interval);
+            let delay = if opt.jitter > 0. {
+                interval + Duration::from_secs_f64(rand::thread_rng().gen_range(0.0..=opt.jitter))
+            } else {
+                interval
+            };
+            if delay > Duration::default() {
+                sleep(delay);
             }
 
-            Ok((page_number, works))
+            let done = info.is_last_page(page_number) || max_works_reached();
+            progress.inc(1);
+            Ok((page_number, received, done))
         })
         .find_first(|result| match result {
             Err(error) => {
-                log::error!("Error: {}", error);
+                log::error!("Error: {:#}", error);
                 true
             }
-            Ok((page_number, works)) => {
-                if works.is_empty() {
+            Ok((page_number, received, done)) => {
+                if *received == 0 {
                     log::info!("Received no works on page {}, stopping", page_number);
                     true
+                } else if *done {
+                    log::info!("Finished on page {}, stopping", page_number);
+                    true
                 } else {
                     false
                 }
             }
         });
+
+    progress.finish_and_clear();
+
+    output
+        .into_inner()
+        .unwrap()
+        .finish()
+        .context("output file")?;
+
+    eprintln!("{}", summary.into_inner().unwrap());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fandom_data::scrape::search_page_to_works;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_output_format() {
+        assert_eq!(output_format(None, None).unwrap(), OutputFormat::Jsonl);
+        assert_eq!(
+            output_format(None, Some(Format::CsvGz)).unwrap(),
+            OutputFormat::Csv
+        );
+        assert_eq!(
+            output_format(Some(&OutputFormat::Csv), None).unwrap(),
+            OutputFormat::Csv
+        );
+        assert_eq!(
+            output_format(Some(&OutputFormat::Jsonl), Some(Format::JsonlGz)).unwrap(),
+            OutputFormat::Jsonl
+        );
+        assert!(output_format(Some(&OutputFormat::Csv), Some(Format::Jsonl)).is_err());
+        assert!(output_format(None, Some(Format::Parquet)).is_err());
+    }
+
+    #[test]
+    fn test_page_list() {
+        assert_eq!(
+            "20-22, 1-3,10,2".parse::<PageList>().unwrap(),
+            PageList(vec![1, 2, 3, 10, 20, 21, 22])
+        );
+        assert_eq!("7".parse::<PageList>().unwrap(), PageList(vec![7]));
+        assert!("0-2".parse::<PageList>().is_err());
+        assert!("5-3".parse::<PageList>().is_err());
+        assert!("1,,2".parse::<PageList>().is_err());
+        assert!("a-b".parse::<PageList>().is_err());
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let rate_limiter = RateLimiter::new(100.).unwrap();
+        let start = Instant::now();
+        for _ in 0..5 {
+            rate_limiter.wait();
+        }
+        // The first request is not delayed
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_rate_limiter_invalid() {
+        assert!(RateLimiter::new(0.).is_err());
+        assert!(RateLimiter::new(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_fetch_summary() {
+        let works = search_page_to_works(include_str!("../search.html")).unwrap();
+        let mut summary = FetchSummary::default();
+        summary.add_page(&works[..2]);
+        summary.add_page(&[]);
+        summary.add_page(&works[2..3]);
+        assert_eq!(summary.works, 3);
+        assert_eq!(summary.pages, 2);
+        assert_eq!(
+            summary.words,
+            works[..3]
+                .iter()
+                .map(|work| u64::from(work.words))
+                .sum::<u64>()
+        );
+        assert_eq!(
+            summary.earliest,
+            works[..3].iter().map(|work| work.date).min()
+        );
+        assert_eq!(
+            summary.latest,
+            works[..3].iter().map(|work| work.date).max()
+        );
+    }
+
+    #[test]
+    fn test_fetch_summary_display() {
+        let summary = FetchSummary {
+            works: 40,
+            pages: 2,
+            earliest: Some(NaiveDate::from_ymd_opt(2020, 11, 30).unwrap()),
+            latest: Some(NaiveDate::from_ymd_opt(2020, 12, 3).unwrap()),
+            words: 123_456,
+        };
+        assert_eq!(
+            summary.to_string(),
+            "Fetched 40 works from 2 pages, dated 2020-11-30 to 2020-12-03, 123456 words in total"
+        );
+        assert_eq!(
+            FetchSummary::default().to_string(),
+            "Fetched 0 works from 0 pages, 0 words in total"
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use anyhow::{Context, Result};
+use fandom_data::{
+    connection::ElasticsearchOpt,
+    search::{ship_co_occurrence, ship_frequencies, CoOccurrence, TagFrequency, TagKind},
+    ship::{Ship, ShipKind},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "graph", about = "Export a relationship network of ao3 data")]
+struct Opt {
+    #[structopt(flatten)]
+    elasticsearch: ElasticsearchOpt,
+
+    /// GraphML file to write
+    #[structopt(long = "output", default_value = "ships.graphml")]
+    output: PathBuf,
+
+    /// Minimum number of works a ship must have to be included
+    #[structopt(long = "min-works", default_value = "50")]
+    min_works: usize,
+
+    /// Maximum number of ships to include
+    #[structopt(long = "limit", default_value = "1000")]
+    limit: usize,
+
+    /// Relationship kind to include.
+    #[structopt(long = "ship-kind", default_value = "romantic")]
+    ship_kind: ShipKind,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let opt = Opt::from_args();
+
+    let client = opt.elasticsearch.client()?;
+
+    let frequencies = ship_frequencies(
+        &client,
+        opt.min_works,
+        opt.limit,
+        TagKind::Relationship,
+        None,
+        Some(&opt.ship_kind),
+    )
+    .await?;
+    let characters = character_works(&frequencies, &opt.ship_kind);
+    let pairs = ship_co_occurrence(frequencies, Some(&opt.ship_kind), false);
+
+    log::info!("Writing {} edges to {:?}", pairs.len(), opt.output);
+    let mut writer = BufWriter::new(File::create(&opt.output).context("output file")?);
+    write_graphml(&mut writer, &characters, &pairs)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Total works of all ships of `ship_kind` each character is in.
+///
+/// Each work counts once for each of its characters, however many others are in the ship.
+fn character_works(frequencies: &[TagFrequency], ship_kind: &ShipKind) -> BTreeMap<String, u64> {
+    let mut characters: BTreeMap<String, u64> = BTreeMap::default();
+    for TagFrequency { tag, count } in frequencies.iter() {
+        // Unparseable ships are logged when splitting them into pairs
+        let ship = match Ship::from_str(tag) {
+            Ok(ship) if &ship.kind == ship_kind => ship,
+            _ => continue,
+        };
+        for character in ship.characters.into_iter().collect::<BTreeSet<_>>() {
+            *characters.entry(character).or_default() += count;
+        }
+    }
+    characters
+}
+
+/// Write an undirected GraphML graph, with characters as nodes and ships as edges.
+///
+/// Each node is weighted by its total works from `character_works`, and each
+/// edge by the works of that ship.
+fn write_graphml<W: Write>(
+    writer: &mut W,
+    characters: &BTreeMap<String, u64>,
+    pairs: &[CoOccurrence],
+) -> Result<()> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    writeln!(
+        writer,
+        r#"  <key id="weight" for="all" attr.name="weight" attr.type="long"/>"#
+    )?;
+    writeln!(writer, r#"  <graph id="ships" edgedefault="undirected">"#)?;
+    for (character, weight) in characters.iter() {
+        writeln!(
+            writer,
+            r#"    <node id="{}"><data key="weight">{}</data></node>"#,
+            escape_xml(character),
+            weight
+        )?;
+    }
+    for pair in pairs.iter() {
+        writeln!(
+            writer,
+            r#"    <edge source="{}" target="{}"><data key="weight">{}</data></edge>"#,
+            escape_xml(&pair.first),
+            escape_xml(&pair.second),
+            pair.count
+        )?;
+    }
+    writeln!(writer, "  </graph>")?;
+    writeln!(writer, "</graphml>")?;
+    Ok(())
+}
+
+/// Escape text for use in XML content or a double quoted attribute.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_character_works() {
+        let frequencies = vec![
+            TagFrequency {
+                tag: "Aang/Katara/Zuko (Avatar)".to_owned(),
+                count: 10,
+            },
+            TagFrequency {
+                tag: "Katara/Zuko (Avatar)".to_owned(),
+                count: 100,
+            },
+            TagFrequency {
+                tag: "Katara & Sokka (Avatar)".to_owned(),
+                count: 50,
+            },
+        ];
+        let characters = character_works(&frequencies, &ShipKind::Romantic);
+        assert_eq!(
+            characters.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("Aang".to_owned(), 10),
+                ("Katara".to_owned(), 110),
+                ("Zuko".to_owned(), 110),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_graphml() {
+        let pairs = vec![
+            CoOccurrence {
+                first: "Katara".to_owned(),
+                second: "Zuko".to_owned(),
+                count: 100,
+            },
+            CoOccurrence {
+                first: "Aang".to_owned(),
+                second: "Katara".to_owned(),
+                count: 30,
+            },
+        ];
+        let characters = vec![
+            ("Aang".to_owned(), 30),
+            ("Katara".to_owned(), 130),
+            ("Zuko".to_owned(), 100),
+        ]
+        .into_iter()
+        .collect();
+        let mut buffer = Vec::new();
+        write_graphml(&mut buffer, &characters, &pairs).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="weight" for="all" attr.name="weight" attr.type="long"/>
+  <graph id="ships" edgedefault="undirected">
+    <node id="Aang"><data key="weight">30</data></node>
+    <node id="Katara"><data key="weight">130</data></node>
+    <node id="Zuko"><data key="weight">100</data></node>
+    <edge source="Katara" target="Zuko"><data key="weight">100</data></edge>
+    <edge source="Aang" target="Katara"><data key="weight">30</data></edge>
+  </graph>
+</graphml>
+"#
+        );
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(
+            escape_xml(r#"Sokka & "Boomerang" <3"#),
+            "Sokka &amp; &quot;Boomerang&quot; &lt;3"
+        );
+    }
+}
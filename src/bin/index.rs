@@ -1,76 +1,41 @@
-use anyhow::{Context, Result};
-use elasticsearch::{
-    http::transport::Transport,
-    indices::{Indices, IndicesPutMappingParts},
-    BulkOperation, BulkOperations, BulkParts, Elasticsearch,
+use anyhow::{anyhow, Context, Result};
+use elasticsearch::Elasticsearch;
+use fandom_data::{
+    connection::ElasticsearchOpt,
+    format::Format,
+    scrape::Work,
+    search::{existing_work_ids, index_works, put_works_mapping},
 };
-use fandom_data::{scrape::Work, search::TagKind};
+use futures::{stream, StreamExt};
 use itertools::Itertools;
-use once_cell::sync::Lazy;
-use serde_json::{json, Value};
 use std::{
-    fs::File,
-    io::{BufRead, BufReader},
+    io::{self, BufRead, BufReader},
     path::PathBuf,
 };
 use structopt::StructOpt;
 
-const WORKS_INDEX: &str = "works";
-
-static MAPPING_WORKS: Lazy<Value> = Lazy::new(|| {
-    json!({
-      "properties": {
-        "id": {
-          "type": "keyword"
-        },
-        "title": {
-          "type": "text"
-        },
-        "author": {
-          "type": "keyword"
-        },
-        TagKind::Relationship.to_field(): {
-          "type": "keyword"
-        },
-        TagKind::Character.to_field(): {
-          "type": "keyword"
-        },
-        TagKind::Freeform.to_field(): {
-          "type": "keyword"
-        },
-        "date": {
-          "type": "date"
-        },
-        "language": {
-          "type": "keyword"
-        },
-        "words": {
-          "type": "long"
-        },
-        "kudos": {
-          "type": "long"
-        },
-        "hits": {
-          "type": "long"
-        },
-      }
-    })
-});
-
 #[derive(Debug, StructOpt)]
 #[structopt(name = "fetch", about = "Fetch ao3 data")]
 struct Opt {
-    /// Works data to index
+    /// Works data to index, as jsonl or CSV by extension, gunzipped if the extension is `.gz`.
+    /// Reads jsonl from stdin if absent or `-`
     #[structopt(long = "input")]
-    input: PathBuf,
+    input: Option<PathBuf>,
 
-    /// Endpoint of elasticsearch cluster
-    #[structopt(long = "elasticsearch")]
-    elasticsearch: String,
+    #[structopt(flatten)]
+    elasticsearch: ElasticsearchOpt,
 
     /// Document chunk size to upload in one request
     #[structopt(long = "chunk-size", default_value = "1024")]
     chunk_size: usize,
+
+    /// Maximum number of chunks to upload at once
+    #[structopt(long = "concurrency", default_value = "4")]
+    concurrency: usize,
+
+    /// Skip works which are already indexed, rather than replacing them
+    #[structopt(long = "only-new")]
+    only_new: bool,
 }
 
 #[tokio::main]
@@ -78,35 +43,72 @@ async fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     let opt = Opt::from_args();
 
-    let transport = Transport::single_node(&opt.elasticsearch)?;
-    let client = Elasticsearch::new(transport);
-    let indices = Indices::new(client.transport());
+    let client = opt.elasticsearch.client()?;
+    put_works_mapping(&client).await?;
 
-    indices
-        .put_mapping(IndicesPutMappingParts::Index(&[WORKS_INDEX]))
-        .body(&*MAPPING_WORKS);
+    let stdin = io::stdin();
+    let (format, file): (Format, Box<dyn BufRead>) = match &opt.input {
+        Some(path) if path.as_os_str() != "-" => {
+            let format = Format::from_path(path)?;
+            (format, format.open(path).context("input file")?)
+        }
+        _ => (Format::Jsonl, Box::new(BufReader::new(stdin.lock()))),
+    };
+    let works: Box<dyn Iterator<Item = Result<Work>>> = match format {
+        Format::Jsonl | Format::JsonlGz => Box::new(file.lines().map(line_to_work)),
+        Format::Csv | Format::CsvGz => {
+            Box::new(Work::read_csv(file).map(|work| work.context("input row")))
+        }
+        Format::Parquet => return Err(anyhow!("Parquet input can't be indexed")),
+    };
+    let chunks = works.chunks(opt.chunk_size);
+    // Works are read and parsed in order, but up to `concurrency` chunks are uploaded at once
+    let uploads = chunks.into_iter().enumerate().map(|(chunk_index, works)| {
+        let works = works.collect();
+        upload_chunk(&client, chunk_index, opt.chunk_size, works, opt.only_new)
+    });
+
+    let mut uploads = stream::iter(uploads).buffer_unordered(opt.concurrency);
+    while let Some(result) = uploads.next().await {
+        result?;
+    }
 
-    let file = BufReader::new(File::open(opt.input).context("input file")?);
-    for (chunk_index, lines) in file.lines().chunks(opt.chunk_size).into_iter().enumerate() {
+    Ok(())
+}
+
+async fn upload_chunk(
+    client: &Elasticsearch,
+    chunk_index: usize,
+    chunk_size: usize,
+    works: Result<Vec<Work>>,
+    only_new: bool,
+) -> Result<()> {
+    let mut works = works.with_context(|| format!("read chunk {}", chunk_index))?;
+    if only_new {
+        let ids: Vec<&str> = works.iter().map(|work| work.id.as_str()).collect();
+        let existing = existing_work_ids(client, &ids)
+            .await
+            .with_context(|| format!("check chunk {}", chunk_index))?;
+        works.retain(|work| !existing.contains(&work.id));
         log::info!(
-            "Processing chunk {} ({} documents)",
-            chunk_index,
-            (chunk_index + 1) * opt.chunk_size
+            "Skipping {} works already indexed in chunk {}",
+            existing.len(),
+            chunk_index
         );
-        let mut ops = BulkOperations::new();
-        for line in lines.into_iter() {
-            let work: Work =
-                serde_json::from_str(&line.context("input line")?).context("line json")?;
-            let id = work.id.clone();
-            ops.push(BulkOperation::index(work).id(id))?;
+        if works.is_empty() {
+            return Ok(());
         }
-
-        client
-            .bulk(BulkParts::Index("works"))
-            .body(vec![ops])
-            .send()
-            .await?;
     }
+    log::info!(
+        "Processing chunk {} ({} documents)",
+        chunk_index,
+        (chunk_index + 1) * chunk_size
+    );
+    index_works(client, works)
+        .await
+        .with_context(|| format!("upload chunk {}", chunk_index))
+}
 
-    Ok(())
+fn line_to_work(line: io::Result<String>) -> Result<Work> {
+    serde_json::from_str(&line.context("input line")?).context("line json")
 }
@@ -0,0 +1,40 @@
+use anyhow::Result;
+use fandom_data::{connection::ElasticsearchOpt, search::language_frequencies};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "languages",
+    about = "Show the language distribution of ao3 data"
+)]
+struct Opt {
+    #[structopt(flatten)]
+    elasticsearch: ElasticsearchOpt,
+
+    /// Maximum number of languages to display
+    #[structopt(long = "limit", default_value = "100")]
+    limit: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let opt = Opt::from_args();
+
+    let client = opt.elasticsearch.client()?;
+
+    let languages = language_frequencies(&client, opt.limit).await?;
+    let total: u64 = languages.iter().map(|language| language.count).sum();
+
+    println!("| Language | Works | Share |");
+    println!("| --- | ---: | ---: |");
+    for language in languages.iter() {
+        println!(
+            "| {} | {} | {:.1}% |",
+            language.tag,
+            language.count,
+            language.count as f64 * 100. / total as f64
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use anyhow::{anyhow, Context, Result};
+use fandom_data::{format::Format, scrape::Work};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "merge", about = "Merge ao3 data files without duplicates")]
+struct Opt {
+    /// Works data to merge, as jsonl or CSV by extension, gunzipped if the extension is `.gz`
+    #[structopt(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// File to write merged works to, as jsonl or CSV by extension, gzipped if the extension
+    /// is `.gz`
+    #[structopt(long = "output")]
+    output: PathBuf,
+}
+
+fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let opt = Opt::from_args();
+    let output_format = Format::from_path(&opt.output)?;
+    if output_format == Format::Parquet {
+        return Err(anyhow!(
+            "Can't merge into parquet, convert the merged works instead"
+        ));
+    }
+
+    let mut works = HashMap::new();
+    for path in opt.inputs.iter() {
+        log::info!("Reading {:?}", path);
+        for work in read_works(path)? {
+            insert_latest(&mut works, work?);
+        }
+    }
+
+    let works = sorted_works(works);
+    log::info!("Writing {} works to {:?}", works.len(), opt.output);
+    let mut output = output_format.create(&opt.output).context("output file")?;
+    if output_format.is_csv() {
+        let mut writer = csv::Writer::from_writer(&mut output);
+        Work::write_csv_header(&mut writer)?;
+        for work in works.iter() {
+            work.write_csv_record(&mut writer)?;
+        }
+        writer.flush()?;
+    } else {
+        for work in works.iter() {
+            serde_json::to_writer(&mut output, work)?;
+            output.write_all(b"\n")?;
+        }
+    }
+    output.finish().context("output file")?;
+    Ok(())
+}
+
+/// Read the works in `path`, with the line or row of any which fail to parse.
+fn read_works(path: &Path) -> Result<Box<dyn Iterator<Item = Result<Work>>>> {
+    let format = Format::from_path(path)?;
+    let reader = format
+        .open(path)
+        .with_context(|| format!("input file {:?}", path))?;
+    let path = path.to_owned();
+    Ok(match format {
+        Format::Jsonl | Format::JsonlGz => {
+            Box::new(reader.lines().enumerate().map(move |(index, line)| {
+                serde_json::from_str(&line.context("input line")?)
+                    .with_context(|| format!("{:?} line {}", path, index + 1))
+            }))
+        }
+        Format::Csv | Format::CsvGz => Box::new(Work::read_csv(reader).enumerate().map(
+            move |(index, work)| work.with_context(|| format!("{:?} row {}", path, index + 1)),
+        )),
+        Format::Parquet => return Err(anyhow!("Can't merge parquet input {:?}", path)),
+    })
+}
+
+/// Insert a work, replacing any work with the same id unless that one has a later date.
+///
+/// Where dates are equal, the work read last wins.
+fn insert_latest(works: &mut HashMap<String, Work>, work: Work) {
+    match works.entry(work.id.clone()) {
+        Entry::Occupied(mut entry) => {
+            if work.date >= entry.get().date {
+                entry.insert(work);
+            }
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(work);
+        }
+    }
+}
+
+/// Sort works by id, ordering numeric ids by value rather than as strings.
+fn sorted_works(works: HashMap<String, Work>) -> Vec<Work> {
+    let mut works: Vec<Work> = works.into_values().collect();
+    works.sort_by(|a, b| (a.id.len(), &a.id).cmp(&(b.id.len(), &b.id)));
+    works
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use pretty_assertions::assert_eq;
+
+    fn test_works() -> Vec<Work> {
+        serde_json::from_str(include_str!("../search.json")).unwrap()
+    }
+
+    #[test]
+    fn test_insert_latest() {
+        let mut works = HashMap::new();
+        let mut older = test_works().remove(0);
+        older.date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        older.kudos = 1;
+        let newer = test_works().remove(0);
+        insert_latest(&mut works, newer);
+        insert_latest(&mut works, older);
+        assert_eq!(works.len(), 1);
+        assert_eq!(
+            works["27871338"].date,
+            NaiveDate::from_ymd_opt(2020, 12, 3).unwrap()
+        );
+        assert_eq!(works["27871338"].kudos, 18);
+    }
+
+    #[test]
+    fn test_sorted_works() {
+        let mut works = HashMap::new();
+        for (id, mut work) in ["100", "9", "20"].iter().zip(test_works()) {
+            work.id = id.to_string();
+            insert_latest(&mut works, work);
+        }
+        let ids: Vec<String> = sorted_works(works)
+            .into_iter()
+            .map(|work| work.id)
+            .collect();
+        assert_eq!(ids, vec!["9", "20", "100"]);
+    }
+}
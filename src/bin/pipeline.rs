@@ -0,0 +1,85 @@
+use anyhow::Result;
+use fandom_data::{
+    connection::ElasticsearchOpt,
+    scrape::{search_page_info, search_page_to_works_lenient, SearchOpt, ENDPOINT_AO3},
+    search::{index_works, put_works_mapping},
+};
+use reqwest::Client;
+use std::time::Duration;
+use structopt::StructOpt;
+use tokio_compat_02::FutureExt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "pipeline", about = "Fetch ao3 data and index it")]
+struct Opt {
+    #[structopt(flatten)]
+    elasticsearch: ElasticsearchOpt,
+
+    #[structopt(flatten)]
+    search: SearchOpt,
+
+    /// Number of works to index in one request
+    #[structopt(long = "chunk-size", default_value = "1024")]
+    chunk_size: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let opt = Opt::from_args();
+    // Requests are made with clients built on tokio 0.2
+    run(opt).compat().await
+}
+
+async fn run(opt: Opt) -> Result<()> {
+    let elasticsearch = opt.elasticsearch.client()?;
+    put_works_mapping(&elasticsearch).await?;
+
+    let params = opt.search.page_url_params();
+
+    let interval = opt.search.interval.map(Duration::from_secs);
+    let client = Client::builder()
+        .user_agent(&opt.search.user_agent)
+        .build()?;
+
+    // Works are buffered across pages, so each bulk request is a full chunk
+    let mut buffer = Vec::with_capacity(opt.chunk_size);
+    for page_number in opt.search.start..opt.search.start + opt.search.count {
+        log::info!("Processing page {}", page_number);
+        let url = params.build(ENDPOINT_AO3, page_number)?;
+        let html = client.get(url).send().await?.text().await?;
+        let info = search_page_info(&html);
+        let (works, errors) = if info.no_results {
+            (Vec::new(), Vec::new())
+        } else {
+            search_page_to_works_lenient(&html)
+        };
+        for error in errors.iter() {
+            log::warn!("Skipping work on page {}: {}", page_number, error);
+        }
+        if works.is_empty() {
+            log::info!("Received no works on page {}, stopping", page_number);
+            break;
+        }
+
+        buffer.extend(works);
+        if buffer.len() >= opt.chunk_size {
+            log::info!("Indexing {} works", buffer.len());
+            index_works(&elasticsearch, std::mem::take(&mut buffer)).await?;
+        }
+        if info.is_last_page(page_number) {
+            log::info!("Reached the last page {}, stopping", page_number);
+            break;
+        }
+
+        if let Some(interval) = interval {
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    if !buffer.is_empty() {
+        log::info!("Indexing {} works", buffer.len());
+        index_works(&elasticsearch, buffer).await?;
+    }
+    Ok(())
+}
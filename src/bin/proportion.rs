@@ -1,127 +1,87 @@
-use anyhow::{Context, Result};
-use chrono::{Date, NaiveDateTime, TimeZone, Utc};
-use elasticsearch::{http::transport::Transport, Elasticsearch, SearchParts};
-use fandom_data::search::TagKind;
-use plotters::prelude::*;
-use serde_json::{json, Value};
+use anyhow::{anyhow, Context, Error, Result};
+use chrono::{Duration, NaiveDate};
+use fandom_data::{
+    connection::ElasticsearchOpt,
+    search::{ship_histogram, Interval},
+};
+use plotters::{
+    coord::{
+        ranged1d::{Ranged, ValueFormatter},
+        types::RangedDate,
+        Shift,
+    },
+    prelude::*,
+};
+use std::{collections::HashMap, ffi::OsStr, path::PathBuf, str::FromStr};
 use structopt::StructOpt;
 
-const WORKS_INDEX: &str = "works";
-const AGGREGATION_KEY: &str = "aggregation_key";
-
 #[derive(Debug, StructOpt)]
 #[structopt(name = "fetch", about = "Fetch ao3 data")]
 struct Opt {
-    /// Endpoint of elasticsearch cluster
-    #[structopt(long = "elasticsearch")]
-    elasticsearch: String,
+    #[structopt(flatten)]
+    elasticsearch: ElasticsearchOpt,
 
     /// Maximum number of ships to display
     #[structopt(long = "limit", default_value = "5")]
     limit: usize,
+
+    /// Width of each histogram bucket: day, week, month or year
+    #[structopt(long = "interval", default_value = "month")]
+    interval: Interval,
+
+    /// Chart file to write, as SVG if the extension is `.svg` and a bitmap otherwise
+    #[structopt(long = "output", default_value = "proportion.png")]
+    output: PathBuf,
+
+    /// Width of the chart in pixels
+    #[structopt(long = "width", default_value = "1024")]
+    width: u32,
+
+    /// Height of the chart in pixels
+    #[structopt(long = "height", default_value = "768")]
+    height: u32,
+
+    /// First year to show, instead of the year of the earliest data
+    #[structopt(long = "from-year")]
+    from_year: Option<i32>,
+
+    /// Last year to show, instead of the year of the latest data
+    #[structopt(long = "to-year")]
+    to_year: Option<i32>,
+
+    /// Top of the count axis, instead of the largest count plus some headroom
+    #[structopt(long = "max-count")]
+    max_count: Option<u64>,
+
+    /// Plot each ship's share of the works shown in each bucket, rather than raw counts
+    #[structopt(long = "normalize")]
+    normalize: bool,
+
+    /// Number of buckets to average each point over, centred on it. 1 plots the raw series
+    #[structopt(long = "smooth", default_value = "1")]
+    smooth: usize,
+
+    /// Chart style: line, or area to stack each ship on top of the last
+    #[structopt(long = "style", default_value = "line")]
+    style: ChartStyle,
 }
 
-/// Load timeseries points of counts of works over time.
-///
-/// Returns:
-///
-/// - a list of `(ship tag, Vec<(date, count)>)`
-async fn ship_histogram(
-    client: &Elasticsearch,
-    limit: usize,
-) -> Result<Vec<(String, Vec<(Date<Utc>, u64)>)>> {
-    let response = client
-        .search(SearchParts::Index(&[WORKS_INDEX]))
-        .body(json!({
-          "aggs": {
-            AGGREGATION_KEY: {
-              "terms": {
-                "field": TagKind::Relationship.to_keyword_field(),
-                "order": {
-                  "_count": "desc"
-                },
-                "size": limit,
-              },
-              "aggs": {
-                AGGREGATION_KEY: {
-                  "date_histogram": {
-                    "field": "date",
-                    "calendar_interval": "1M",
-                    "min_doc_count": 0
-                  }
-                }
-              }
-            }
-          },
-          "size": 0,
-          "docvalue_fields": [
-            {
-              "field": "date",
-              "format": "date_time"
-            }
-          ],
-          "query": {
-              "match_all": {}
-          }
-        }))
-        .allow_no_indices(true)
-        .send()
-        .await?;
-
-    let response_body = response.json::<Value>().await?;
-    let buckets = response_body
-        .get("aggregations")
-        .context("Response aggregations key")?
-        .get(AGGREGATION_KEY)
-        .context("Response aggregation key")?
-        .get("buckets")
-        .context("Response buckets key")?
-        .as_array()
-        .context("Response buckets array")?;
-    Ok(buckets
-        .iter()
-        .map(|bucket| {
-            Ok((
-                bucket
-                    .get("key")
-                    .context("bucket key")?
-                    .as_str()
-                    .context("bucket key string")?
-                    .to_owned(),
-                bucket
-                    .get(AGGREGATION_KEY)
-                    .context("bucket sub aggregation")?
-                    .get("buckets")
-                    .context("sub agg buckets key")?
-                    .as_array()
-                    .context("sub agg buckets array")?
-                    .iter()
-                    .map(|bucket| {
-                        Ok((
-                            Date::from_utc(
-                                NaiveDateTime::from_timestamp(
-                                    (bucket
-                                        .get("key")
-                                        .context("sub key")?
-                                        .as_u64()
-                                        .context("sub key as int")?
-                                        / 1000) as i64,
-                                    0,
-                                )
-                                .date(),
-                                Utc,
-                            ),
-                            bucket
-                                .get("doc_count")
-                                .context("bucket doc count")?
-                                .as_u64()
-                                .context("bucket doc count integer")?,
-                        ))
-                    })
-                    .collect::<Result<_>>()?,
-            ))
-        })
-        .collect::<Result<_>>()?)
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum ChartStyle {
+    Line,
+    Area,
+}
+
+impl FromStr for ChartStyle {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "line" => Ok(Self::Line),
+            "area" => Ok(Self::Area),
+            _ => Err(anyhow!("Invalid chart style: '{}'", string)),
+        }
+    }
 }
 
 #[tokio::main]
@@ -129,41 +89,161 @@ async fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     let opt = Opt::from_args();
 
-    let transport = Transport::single_node(&opt.elasticsearch)?;
-    let client = Elasticsearch::new(transport);
+    let client = opt.elasticsearch.client()?;
 
-    let results = ship_histogram(&client, opt.limit).await?;
+    let results = ship_histogram(&client, opt.limit, &opt.interval).await?;
+    let mut results: Vec<(String, Vec<(NaiveDate, f64)>)> = results
+        .into_iter()
+        .map(|histogram| {
+            (
+                histogram.tag,
+                histogram
+                    .counts
+                    .into_iter()
+                    .map(|(date, count)| (date, count as f64))
+                    .collect(),
+            )
+        })
+        .collect();
+    if opt.normalize {
+        normalize(&mut results);
+    }
+    if opt.smooth > 1 {
+        for (_ship_name, data) in results.iter_mut() {
+            smooth(data, opt.smooth);
+        }
+    }
+    if opt.style == ChartStyle::Area {
+        stack(&mut results);
+    }
 
     log::info!("Plotting chart");
-    let root = BitMapBackend::new("proportion.png", (1024, 768)).into_drawing_area();
+    let size = (opt.width, opt.height);
+    if opt.output.extension().and_then(OsStr::to_str) == Some("svg") {
+        plot(
+            SVGBackend::new(&opt.output, size).into_drawing_area(),
+            &opt,
+            results,
+        )
+    } else {
+        plot(
+            BitMapBackend::new(&opt.output, size).into_drawing_area(),
+            &opt,
+            results,
+        )
+    }
+}
 
+fn plot<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    opt: &Opt,
+    results: Vec<(String, Vec<(NaiveDate, f64)>)>,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let dates = results
+        .iter()
+        .flat_map(|(_ship_name, data)| data.iter().map(|(date, _count)| *date));
+    let from = match opt.from_year {
+        Some(year) => NaiveDate::from_ymd_opt(year, 1, 1).unwrap(),
+        None => dates.clone().min().context("no data to plot")?,
+    };
+    let to = match opt.to_year {
+        Some(year) => NaiveDate::from_ymd_opt(year, 12, 31).unwrap(),
+        None => dates.max().context("no data to plot")?,
+    };
+    let max_value = if opt.normalize {
+        1.
+    } else {
+        match opt.max_count {
+            Some(max_count) => max_count as f64,
+            // Leave 10% headroom above the highest point
+            None => {
+                let max_count = results
+                    .iter()
+                    .flat_map(|(_ship_name, data)| data.iter().map(|(_date, count)| *count))
+                    .fold(0., f64::max);
+                (max_count * 1.1).max(1.)
+            }
+        }
+    };
+
+    // Fewer, coarser labels for longer spans of time, so they stay legible
+    let span = to.signed_duration_since(from);
+    if span > Duration::days(3 * 365) {
+        draw(root, opt, (from..to).yearly(), max_value, results)
+    } else if span > Duration::days(90) {
+        draw(root, opt, (from..to).monthly(), max_value, results)
+    } else {
+        draw(root, opt, RangedDate::from(from..to), max_value, results)
+    }
+}
+
+fn draw<DB: DrawingBackend, X>(
+    root: DrawingArea<DB, Shift>,
+    opt: &Opt,
+    x_range: X,
+    max_value: f64,
+    results: Vec<(String, Vec<(NaiveDate, f64)>)>,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+    X: Ranged<ValueType = NaiveDate> + ValueFormatter<NaiveDate>,
+{
     root.fill(&WHITE)?;
 
     let mut chart = ChartBuilder::on(&root)
         .margin(10)
-        .caption("Monthly Count of Ship Works", ("sans-serif", 40))
+        .caption(
+            format!(
+                "{} {} of Ship Works",
+                opt.interval.to_adjective(),
+                if opt.normalize { "Share" } else { "Count" }
+            ),
+            ("sans-serif", 40),
+        )
         .set_label_area_size(LabelAreaPosition::Left, 60)
         .set_label_area_size(LabelAreaPosition::Right, 60)
         .set_label_area_size(LabelAreaPosition::Bottom, 40)
-        .build_cartesian_2d(
-            (Utc.ymd(2008, 1, 1)..Utc.ymd(2020, 12, 1)).yearly(),
-            0u64..600u64,
-        )?;
+        .build_cartesian_2d(x_range, 0f64..max_value)?;
 
     chart
         .configure_mesh()
         .disable_x_mesh()
         .disable_y_mesh()
         .x_labels(30)
-        .y_desc("Work Count")
+        .y_desc(if opt.normalize {
+            "Share of Works"
+        } else {
+            "Work Count"
+        })
         .draw()?;
 
-    for (index, (ship_name, data)) in results.into_iter().enumerate() {
-        let color = Palette99::pick(index);
-        chart
-            .draw_series(LineSeries::new(data.into_iter(), &color))?
-            .label(&ship_name)
-            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+    match opt.style {
+        ChartStyle::Line => {
+            for (index, (ship_name, data)) in results.into_iter().enumerate() {
+                let color = Palette99::pick(index);
+                chart
+                    .draw_series(LineSeries::new(data, &color))?
+                    .label(&ship_name)
+                    .legend(move |(x, y)| {
+                        Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled())
+                    });
+            }
+        }
+        ChartStyle::Area => {
+            // Areas are filled down to zero, so draw the highest first to leave every band visible
+            for (index, (ship_name, data)) in results.into_iter().enumerate().rev() {
+                let color = Palette99::pick(index);
+                chart
+                    .draw_series(AreaSeries::new(data, 0., color.mix(0.8)).border_style(&color))?
+                    .label(&ship_name)
+                    .legend(move |(x, y)| {
+                        Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled())
+                    });
+            }
+        }
     }
 
     chart
@@ -172,5 +252,144 @@ async fn main() -> Result<()> {
         .border_style(&BLACK)
         .draw()?;
 
+    root.present()?;
     Ok(())
 }
+
+/// Replace each count with its share of the total count in that bucket.
+///
+/// The total is over the ships given, so shares in each bucket sum to 1.0.
+fn normalize(results: &mut [(String, Vec<(NaiveDate, f64)>)]) {
+    let mut totals: HashMap<NaiveDate, f64> = HashMap::default();
+    for (_ship_name, data) in results.iter() {
+        for (date, count) in data.iter() {
+            *totals.entry(*date).or_default() += count;
+        }
+    }
+    for (_ship_name, data) in results.iter_mut() {
+        for (date, count) in data.iter_mut() {
+            let total = totals[&*date];
+            if total > 0. {
+                *count /= total;
+            }
+        }
+    }
+}
+
+/// Replace each count with the mean of the `window` counts centred on it.
+///
+/// Near either end of the series, the mean is over the counts which exist.
+fn smooth(data: &mut [(NaiveDate, f64)], window: usize) {
+    let counts: Vec<f64> = data.iter().map(|(_date, count)| *count).collect();
+    for (index, (_date, count)) in data.iter_mut().enumerate() {
+        let start = index.saturating_sub(window / 2);
+        let end = (index + (window - 1) / 2 + 1).min(counts.len());
+        *count = counts[start..end].iter().sum::<f64>() / (end - start) as f64;
+    }
+}
+
+/// Add to each count the counts of all earlier ships in the same bucket, so the series stack.
+fn stack(results: &mut [(String, Vec<(NaiveDate, f64)>)]) {
+    let mut totals: HashMap<NaiveDate, f64> = HashMap::default();
+    for (_ship_name, data) in results.iter_mut() {
+        for (date, count) in data.iter_mut() {
+            let total = totals.entry(*date).or_default();
+            *total += *count;
+            *count = *total;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let january = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let february = NaiveDate::from_ymd_opt(2020, 2, 1).unwrap();
+        let mut results = vec![
+            (
+                "Katara/Zuko".to_owned(),
+                vec![(january, 3.), (february, 0.)],
+            ),
+            (
+                "Aang/Katara".to_owned(),
+                vec![(january, 1.), (february, 0.)],
+            ),
+            ("Sokka/Suki".to_owned(), vec![(february, 5.)]),
+        ];
+        normalize(&mut results);
+        assert_eq!(
+            results,
+            vec![
+                (
+                    "Katara/Zuko".to_owned(),
+                    vec![(january, 0.75), (february, 0.)]
+                ),
+                (
+                    "Aang/Katara".to_owned(),
+                    vec![(january, 0.25), (february, 0.)]
+                ),
+                ("Sokka/Suki".to_owned(), vec![(february, 1.)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stack() {
+        let january = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let february = NaiveDate::from_ymd_opt(2020, 2, 1).unwrap();
+        let mut results = vec![
+            (
+                "Katara/Zuko".to_owned(),
+                vec![(january, 3.), (february, 0.)],
+            ),
+            ("Sokka/Suki".to_owned(), vec![(february, 5.)]),
+            (
+                "Aang/Katara".to_owned(),
+                vec![(january, 1.), (february, 2.)],
+            ),
+        ];
+        stack(&mut results);
+        assert_eq!(
+            results,
+            vec![
+                (
+                    "Katara/Zuko".to_owned(),
+                    vec![(january, 3.), (february, 0.)]
+                ),
+                ("Sokka/Suki".to_owned(), vec![(february, 5.)]),
+                (
+                    "Aang/Katara".to_owned(),
+                    vec![(january, 4.), (february, 7.)]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_smooth() {
+        let dates: Vec<NaiveDate> = (1..=5)
+            .map(|month| NaiveDate::from_ymd_opt(2020, month, 1).unwrap())
+            .collect();
+        let mut data: Vec<(NaiveDate, f64)> = dates
+            .iter()
+            .copied()
+            .zip(vec![3., 0., 6., 3., 9.])
+            .collect();
+        smooth(&mut data, 3);
+        assert_eq!(
+            data,
+            dates
+                .iter()
+                .copied()
+                .zip(vec![1.5, 3., 3., 6., 6.])
+                .collect::<Vec<_>>()
+        );
+
+        let mut raw = vec![(dates[0], 3.), (dates[1], 0.)];
+        smooth(&mut raw, 1);
+        assert_eq!(raw, vec![(dates[0], 3.), (dates[1], 0.)]);
+    }
+}
@@ -1,14 +1,24 @@
-use anyhow::Result;
-use elasticsearch::{http::transport::Transport, Elasticsearch};
-use fandom_data::search::{significant_tags, TagKind};
+use anyhow::{anyhow, Error, Result};
+use fandom_data::{
+    connection::ElasticsearchOpt,
+    search::{
+        avg_words_per_tag, cardinality, ship_word_count_percentiles, significant_tags,
+        sum_metric_per_tag, word_count_percentiles, SignificantTags, TagKind, TagPercentiles,
+        WorkMetric,
+    },
+};
+use std::{collections::HashMap, str::FromStr};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "fetch", about = "Fetch ao3 data")]
 struct Opt {
-    /// Endpoint of elasticsearch cluster
-    #[structopt(long = "elasticsearch")]
-    elasticsearch: String,
+    #[structopt(flatten)]
+    elasticsearch: ElasticsearchOpt,
+
+    /// Minimum number of works a ship must have to be displayed
+    #[structopt(long = "min-works", default_value = "50")]
+    min_works: usize,
 
     /// Maximum number of ships to display
     #[structopt(long = "limit", default_value = "5")]
@@ -17,6 +27,57 @@ struct Opt {
     /// Tag kind to show significant terms for.
     #[structopt(long = "tag-kind", default_value = "relationship")]
     tag_kind: TagKind,
+
+    /// Tag kind to find significant terms within each of, such as each ship.
+    #[structopt(long = "group-by", default_value = "relationship")]
+    group_by: TagKind,
+
+    /// Print the approximate number of unique tags of this kind instead.
+    #[structopt(long = "count-unique")]
+    count_unique: bool,
+
+    /// Print word count percentiles, overall and per ship, instead.
+    #[structopt(long = "stats")]
+    stats: bool,
+
+    /// Print the average word count of works with each tag of this kind instead, longest first.
+    #[structopt(long = "avg-words")]
+    avg_words: bool,
+
+    /// Print the total of this metric over works with each tag of this kind instead, largest
+    /// first: words, kudos, hits, comments or bookmarks.
+    #[structopt(long = "sum")]
+    sum: Option<WorkMetric>,
+
+    /// Percentiles of word count to print with `--stats`.
+    #[structopt(long = "percentiles", default_value = "50,95", use_delimiter = true)]
+    percentiles: Vec<f64>,
+
+    /// Minimum score of significant tags to display
+    #[structopt(long = "min-score")]
+    min_score: Option<f64>,
+
+    /// Output format for significant tags: markdown or json
+    #[structopt(long = "format", default_value = "markdown")]
+    format: OutputFormat,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum OutputFormat {
+    Markdown,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "markdown" => Ok(Self::Markdown),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow!("Invalid output format: '{}'", string)),
+        }
+    }
 }
 
 #[tokio::main]
@@ -24,18 +85,116 @@ async fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     let opt = Opt::from_args();
 
-    let transport = Transport::single_node(&opt.elasticsearch)?;
-    let client = Elasticsearch::new(transport);
+    let client = opt.elasticsearch.client()?;
+
+    if opt.count_unique {
+        println!("{}", cardinality(&client, opt.tag_kind).await?);
+        return Ok(());
+    }
+
+    if opt.avg_words {
+        println!("# Average word count\n");
+        let averages = avg_words_per_tag(&client, opt.tag_kind, opt.min_works, opt.limit).await?;
+        for (tag, average) in averages.iter() {
+            println!("- {}: {:.0} words", tag, average);
+        }
+        return Ok(());
+    }
+
+    if let Some(metric) = &opt.sum {
+        println!("# Total {}\n", metric.to_field());
+        let totals =
+            sum_metric_per_tag(&client, opt.tag_kind, metric, opt.min_works, opt.limit).await?;
+        for (tag, total) in totals.iter() {
+            println!("- {}: {:.0}", tag, total);
+        }
+        return Ok(());
+    }
+
+    if opt.stats {
+        println!("# Word count percentiles\n");
+        print_percentiles(word_count_percentiles(&client, &opt.percentiles).await?);
+        let ship_percentiles =
+            ship_word_count_percentiles(&client, opt.min_works, opt.limit, &opt.percentiles)
+                .await?;
+        for TagPercentiles { tag, percentiles } in ship_percentiles {
+            println!("## {}\n", tag);
+            print_percentiles(percentiles);
+        }
+        return Ok(());
+    }
 
-    let significant_tags = significant_tags(&client, 50, opt.limit, opt.tag_kind).await?;
+    let mut significant_tags = significant_tags(
+        &client,
+        opt.min_works,
+        opt.limit,
+        opt.group_by,
+        opt.tag_kind,
+    )
+    .await?;
+    if let Some(min_score) = opt.min_score {
+        retain_min_score(&mut significant_tags, min_score);
+    }
+
+    if opt.format == OutputFormat::Json {
+        println!("{}", serde_json::to_string(&significant_tags)?);
+        return Ok(());
+    }
 
     println!("# Significant tags\n");
-    for (ship, tags) in significant_tags.iter() {
-        println!("## {}\n", ship);
-        for tag in tags.iter() {
-            println!("- {}", tag);
+    for ship_tags in significant_tags.iter() {
+        println!("## {}\n", ship_tags.ship);
+        for tag in ship_tags.tags.iter() {
+            println!("- {} ({:.3})", tag.tag, tag.score);
         }
         println!();
     }
     Ok(())
 }
+
+/// Drop significant tags scoring below `min_score`, keeping every group.
+fn retain_min_score(significant_tags: &mut [SignificantTags], min_score: f64) {
+    for group in significant_tags.iter_mut() {
+        group.tags.retain(|tag| tag.score >= min_score);
+    }
+}
+
+fn print_percentiles(percentiles: HashMap<String, f64>) {
+    let mut percentiles: Vec<(f64, f64)> = percentiles
+        .into_iter()
+        .filter_map(|(percentile, value)| Some((percentile.parse().ok()?, value)))
+        .collect();
+    percentiles.sort_by(|(a, _), (b, _)| a.partial_cmp(b).expect("percentile to be a number"));
+    for (percentile, value) in percentiles.iter() {
+        println!("- {}th: {:.0} words", percentile, value);
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fandom_data::search::SignificantTag;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_retain_min_score() {
+        let tag = |tag: &str, score| SignificantTag {
+            tag: tag.to_owned(),
+            score,
+        };
+        let mut significant_tags = vec![
+            SignificantTags {
+                ship: "Katara/Zuko (Avatar)".to_owned(),
+                tags: vec![tag("Fluff", 0.5), tag("Angst", 0.05)],
+            },
+            SignificantTags {
+                ship: "Aang/Katara (Avatar)".to_owned(),
+                tags: vec![tag("Canon", 0.01)],
+            },
+        ];
+        retain_min_score(&mut significant_tags, 0.1);
+        assert_eq!(significant_tags[0].tags, vec![tag("Fluff", 0.5)]);
+        assert!(significant_tags[1].tags.is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::{anyhow, Context, Error, Result};
+use fandom_data::{format::Format, scrape::Work};
+use std::{
+    io::{self, BufRead, BufReader},
+    path::PathBuf,
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "validate", about = "Check ao3 data parses before indexing")]
+struct Opt {
+    /// Works data to check as jsonl, gunzipped if the extension is `.gz`. Reads stdin if absent or `-`
+    #[structopt(long = "input")]
+    input: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let opt = Opt::from_args();
+
+    let stdin = io::stdin();
+    let file: Box<dyn BufRead> = match &opt.input {
+        Some(path) if path.as_os_str() != "-" => {
+            let format = Format::from_path(path)?;
+            if !format.is_jsonl() {
+                return Err(anyhow!("Only jsonl input can be validated, not {:?}", path));
+            }
+            format.open(path).context("input file")?
+        }
+        _ => Box::new(BufReader::new(stdin.lock())),
+    };
+
+    let mut lines = 0;
+    let mut invalid = 0;
+    for (line_number, error) in invalid_lines(file.lines().inspect(|_| lines += 1)) {
+        log::error!("Line {}: {:#}", line_number, error);
+        invalid += 1;
+    }
+
+    log::info!("Checked {} lines, {} invalid", lines, invalid);
+    if invalid > 0 {
+        return Err(anyhow!("{} of {} lines are invalid", invalid, lines));
+    }
+    Ok(())
+}
+
+/// Find lines which do not parse as a `Work`, with their one-based line numbers.
+fn invalid_lines(
+    lines: impl Iterator<Item = io::Result<String>>,
+) -> impl Iterator<Item = (usize, Error)> {
+    lines.enumerate().filter_map(|(index, line)| {
+        line.context("input line")
+            .and_then(|line| serde_json::from_str::<Work>(&line).context("line json"))
+            .err()
+            .map(|error| (index + 1, error))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_invalid_lines() {
+        let works: Vec<Work> = serde_json::from_str(include_str!("../search.json")).unwrap();
+        let work = serde_json::to_string(&works[0]).unwrap();
+        let lines = vec![
+            Ok(work.clone()),
+            Ok(r#"{"id": "1"}"#.to_owned()),
+            Ok(work),
+            Ok("not json".to_owned()),
+        ];
+        let invalid: Vec<usize> = invalid_lines(lines.into_iter())
+            .map(|(line_number, _)| line_number)
+            .collect();
+        assert_eq!(invalid, vec![2, 4]);
+    }
+}
@@ -1,23 +1,43 @@
-use anyhow::{anyhow, Error, Result};
+use anyhow::{anyhow, Context, Error, Result};
 use chord::{Chord, Plot};
-use elasticsearch::{http::transport::Transport, Elasticsearch};
-use fandom_data::search::{ship_frequencies, ShipKind, TagKind};
+use chrono::NaiveDate;
+use fandom_data::{
+    connection::ElasticsearchOpt,
+    search::{
+        co_occurrence, tag_co_occurrence, CoOccurrence, CoOccurrenceWeight, TagKind, WorksQuery,
+    },
+    ship::{Ship, ShipKind},
+};
 use palette::{rgb::LinSrgb, Hsv, IntoColor};
 use serde::Serialize;
 use std::{
     collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
     str::FromStr,
 };
 use structopt::StructOpt;
 
 const GOLDEN_RATIO: f32 = 1.618033;
+/// The Okabe-Ito palette, distinguishable with common forms of color blindness.
+const OKABE_ITO: [(u8, u8, u8); 8] = [
+    (0xE6, 0x9F, 0x00),
+    (0x56, 0xB4, 0xE9),
+    (0x00, 0x9E, 0x73),
+    (0xF0, 0xE4, 0x42),
+    (0x00, 0x72, 0xB2),
+    (0xD5, 0x5E, 0x00),
+    (0xCC, 0x79, 0xA7),
+    (0x00, 0x00, 0x00),
+];
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "fetch", about = "Fetch ao3 data")]
 struct Opt {
-    /// Endpoint of elasticsearch cluster
-    #[structopt(long = "elasticsearch")]
-    elasticsearch: String,
+    #[structopt(flatten)]
+    elasticsearch: ElasticsearchOpt,
 
     /// Minimum number of works a tag must have to be displayed
     #[structopt(long = "min-works", default_value = "50")]
@@ -31,9 +51,123 @@ struct Opt {
     #[structopt(long = "ship-kind", default_value = "romantic")]
     ship_kind: ShipKind,
 
+    /// Tags to pair from: relationships, or characters or freeforms appearing together in a work
+    #[structopt(long = "source", default_value = "relationships")]
+    source: CoOccurrenceSource,
+
+    /// What each work adds to an edge: works, kudos or hits
+    #[structopt(long = "weight", default_value = "works")]
+    weight: CoOccurrenceWeight,
+
+    /// Only count works dated on or after this day, as `YYYY-MM-DD`
+    #[structopt(long = "after")]
+    after: Option<NaiveDate>,
+
+    /// Only count works dated on or before this day, as `YYYY-MM-DD`
+    #[structopt(long = "before")]
+    before: Option<NaiveDate>,
+
     /// Output raw data instead of nice format.
     #[structopt(long = "raw")]
     raw: bool,
+
+    /// Clamp each co-occurrence cell to at most this many works.
+    ///
+    /// Keeps smaller ships visible next to a dominant one, but distorts proportions.
+    #[structopt(long = "cap-edge")]
+    cap_edge: Option<f64>,
+
+    /// Log-scale co-occurrence cells, applied after any cap.
+    ///
+    /// Keeps smaller ships visible next to a dominant one, but distorts proportions.
+    #[structopt(long = "log-edges")]
+    log_edges: bool,
+
+    /// Drop co-occurrence cells of fewer than this many works from the chord diagram.
+    ///
+    /// Characters left without any edges are dropped too. Unlike `--min-works`,
+    /// this applies after ship tags are collated into pairs of characters.
+    #[structopt(long = "min-edge", default_value = "0")]
+    min_edge: u64,
+
+    /// Only show this many characters in the chord diagram, by total works across their ships.
+    ///
+    /// Ships involving any other character are dropped.
+    #[structopt(long = "top-characters")]
+    top_characters: Option<usize>,
+
+    /// Only show ships involving this character, matched case-insensitively.
+    #[structopt(long = "focus")]
+    focus: Option<String>,
+
+    /// Also write the co-occurrence matrix of the chord diagram to this CSV file, before any
+    /// edge scaling. Written with `--raw` too
+    #[structopt(long = "matrix-csv")]
+    matrix_csv: Option<PathBuf>,
+
+    /// JSON object or two column CSV file mapping character names to a canonical name.
+    ///
+    /// Names without an alias are kept as they are.
+    #[structopt(long = "aliases")]
+    aliases: Option<PathBuf>,
+
+    /// Colors for characters: golden, pastel or colorblind
+    #[structopt(long = "palette", default_value = "golden")]
+    palette: ColorPalette,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum CoOccurrenceSource {
+    Relationships,
+    Characters,
+    Freeforms,
+}
+
+impl FromStr for CoOccurrenceSource {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "relationships" => Ok(Self::Relationships),
+            "characters" => Ok(Self::Characters),
+            "freeforms" => Ok(Self::Freeforms),
+            _ => Err(anyhow!("Invalid co-occurrence source: '{}'", string)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum ColorPalette {
+    Golden,
+    Pastel,
+    Colorblind,
+}
+
+impl FromStr for ColorPalette {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "golden" => Ok(Self::Golden),
+            "pastel" => Ok(Self::Pastel),
+            "colorblind" => Ok(Self::Colorblind),
+            _ => Err(anyhow!("Invalid color palette: '{}'", string)),
+        }
+    }
+}
+
+impl ColorPalette {
+    /// Color of the item at `index`, cycling if the palette has a fixed size.
+    fn color(&self, index: usize) -> LinSrgb<u8> {
+        match self {
+            Self::Golden => golden_color(index, 0.68, 0.69),
+            Self::Pastel => golden_color(index, 0.35, 0.95),
+            Self::Colorblind => {
+                let (red, green, blue) = OKABE_ITO[index % OKABE_ITO.len()];
+                LinSrgb::new(red, green, blue)
+            }
+        }
+    }
 }
 
 #[tokio::main]
@@ -41,52 +175,93 @@ async fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     let opt = Opt::from_args();
 
-    let transport = Transport::single_node(&opt.elasticsearch)?;
-    let client = Elasticsearch::new(transport);
-
-    let results = ship_frequencies(
-        &client,
-        opt.min_works,
-        opt.limit,
-        TagKind::Relationship,
-        None,
-    )
-    .await?;
-
-    // We key by parsed ship type to collate duplicates
-    let mut freqs: HashMap<Ship, u64> = HashMap::default();
-    for (ship, count) in results
-        .into_iter()
-        .filter_map(|(ship, count)| {
-            Ship::from_str(&ship)
-                // A bit of munging - we can't handle tags where we don't have 2 characters
-                .and_then(|ship| {
-                    if ship.characters.len() == 2 {
-                        Ok(ship)
-                    } else {
-                        Err(anyhow!(
-                            "Ship must have exactly two characters: '{:?}'",
-                            ship.characters
-                        ))
-                    }
-                })
-                .map_err(|error| {
-                    log::warn!("Dropping ship: {}", error);
-                    error
-                })
-                .ok()
-                .map(|ship| (ship, count))
-        })
-        .filter(|(ship, _count)| ship.kind == opt.ship_kind)
-    {
-        // Add rather than assigning here, to allow for duplicate ship tags
-        *freqs.entry(ship).or_default() += count;
-    }
+    let client = opt.elasticsearch.client()?;
+
+    let filter = WorksQuery::default().date_range(opt.after, opt.before);
+    let pairs = match opt.source {
+        // Ships are split into pairs of characters, with duplicate tags collated
+        CoOccurrenceSource::Relationships => {
+            co_occurrence(
+                &client,
+                TagKind::Relationship,
+                opt.min_works,
+                opt.limit,
+                Some(filter),
+                Some(opt.ship_kind.clone()),
+                true,
+                &opt.weight,
+            )
+            .await?
+        }
+        CoOccurrenceSource::Characters | CoOccurrenceSource::Freeforms => {
+            let field = if opt.source == CoOccurrenceSource::Characters {
+                TagKind::Character
+            } else {
+                TagKind::Freeform
+            };
+            tag_co_occurrence(
+                &client,
+                field,
+                opt.min_works,
+                opt.limit,
+                Some(filter),
+                &opt.weight,
+            )
+            .await?
+        }
+    };
+    let pairs = match &opt.aliases {
+        Some(path) => apply_aliases(pairs, &load_aliases(path)?),
+        None => pairs,
+    };
+    let pairs = match &opt.focus {
+        Some(focus) => focus_on(pairs, focus)?,
+        None => pairs,
+    };
+
+    // The matrix is the one the chord diagram would draw, even if only raw data is output
+    let chord_matrix = |pairs: Vec<CoOccurrence>| {
+        let pairs = match opt.top_characters {
+            Some(top_characters) => keep_top_characters(pairs, top_characters),
+            None => pairs,
+        };
+        co_occurrence_matrix(&drop_small_edges(pairs, opt.min_edge))
+    };
+    let write_matrix = |names: &[String], matrix: &[Vec<f64>]| -> Result<()> {
+        if let Some(path) = &opt.matrix_csv {
+            write_matrix_csv(
+                File::create(path).context("matrix csv file")?,
+                names,
+                matrix,
+            )?;
+        }
+        Ok(())
+    };
 
     if opt.raw {
-        output_raw(freqs)?;
+        if opt.matrix_csv.is_some() {
+            let (names, matrix) = chord_matrix(pairs.clone());
+            write_matrix(&names, &matrix)?;
+        }
+        match opt.source {
+            CoOccurrenceSource::Relationships => output_raw(pairs, &opt.ship_kind)?,
+            // Tags appearing together are not a ship of any kind
+            CoOccurrenceSource::Characters | CoOccurrenceSource::Freeforms => {
+                println!("{}", serde_json::to_string(&pairs)?)
+            }
+        }
     } else {
-        output_chord(freqs);
+        let (names, matrix) = chord_matrix(pairs);
+        write_matrix(&names, &matrix)?;
+        output_chord(
+            names,
+            matrix,
+            &EdgeScale {
+                cap: opt.cap_edge,
+                log: opt.log_edges,
+            },
+            &opt.palette,
+        );
     }
 
     Ok(())
@@ -98,23 +273,167 @@ struct ShipCount {
     count: u64,
 }
 
-fn output_raw(freqs: HashMap<Ship, u64>) -> Result<()> {
-    let mut sorted_by_count: Vec<ShipCount> = freqs
+fn output_raw(pairs: Vec<CoOccurrence>, kind: &ShipKind) -> Result<()> {
+    let mut sorted_by_count: Vec<ShipCount> = pairs
         .into_iter()
-        .map(|(ship, count)| ShipCount { ship, count })
+        .map(|pair| ShipCount {
+            ship: Ship {
+                characters: vec![pair.first, pair.second],
+                kind: kind.clone(),
+            },
+            count: pair.count,
+        })
         .collect();
     sorted_by_count.sort();
     println!("{}", serde_json::to_string(&sorted_by_count)?);
     Ok(())
 }
 
-fn output_chord(freqs: HashMap<Ship, u64>) {
+/// Load a map of character names to canonical names, from CSV if the extension is `.csv`
+/// and JSON otherwise.
+fn load_aliases(path: &Path) -> Result<HashMap<String, String>> {
+    let file = File::open(path).context("aliases file")?;
+    if path.extension().and_then(OsStr::to_str) == Some("csv") {
+        read_aliases_csv(file)
+    } else {
+        serde_json::from_reader(file).context("aliases json")
+    }
+}
+
+/// Read aliases from CSV rows of a raw name then its canonical name, without a header.
+fn read_aliases_csv<R: Read>(reader: R) -> Result<HashMap<String, String>> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(reader)
+        .deserialize()
+        .map(|record| record.context("aliases csv"))
+        .collect()
+}
+
+/// Rename characters to their canonical names, then collate pairs which now match.
+///
+/// Pairs which become a character paired with itself are dropped.
+fn apply_aliases(pairs: Vec<CoOccurrence>, aliases: &HashMap<String, String>) -> Vec<CoOccurrence> {
+    let canonical = |name: String| aliases.get(&name).cloned().unwrap_or(name);
+    let mut counts: HashMap<(String, String), u64> = HashMap::default();
+    for pair in pairs.into_iter() {
+        let first = canonical(pair.first);
+        let second = canonical(pair.second);
+        if first == second {
+            continue;
+        }
+        let key = if first < second {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        *counts.entry(key).or_default() += pair.count;
+    }
+    let mut pairs: Vec<CoOccurrence> = counts
+        .into_iter()
+        .map(|((first, second), count)| CoOccurrence {
+            first,
+            second,
+            count,
+        })
+        .collect();
+    pairs.sort_unstable();
+    pairs
+}
+
+/// Keep only pairs involving the `focus` character, ignoring case.
+///
+/// Errors if there are no such pairs, as the output would be empty.
+fn focus_on(pairs: Vec<CoOccurrence>, focus: &str) -> Result<Vec<CoOccurrence>> {
+    let focus_lowercase = focus.to_lowercase();
+    let pairs: Vec<CoOccurrence> = pairs
+        .into_iter()
+        .filter(|pair| {
+            pair.first.to_lowercase() == focus_lowercase
+                || pair.second.to_lowercase() == focus_lowercase
+        })
+        .collect();
+    if pairs.is_empty() {
+        return Err(anyhow!(
+            "No ships found for focus character '{}' with the given --min-works",
+            focus
+        ));
+    }
+    Ok(pairs)
+}
+
+/// Remove pairs with fewer than `min_edge` works.
+///
+/// Pairs are already collated, so each is a single cell of the chord matrix.
+fn drop_small_edges(pairs: Vec<CoOccurrence>, min_edge: u64) -> Vec<CoOccurrence> {
+    pairs
+        .into_iter()
+        .filter(|pair| pair.count >= min_edge)
+        .collect()
+}
+
+/// Remove pairs involving any character outside the `limit` with the most works.
+///
+/// A character's works are summed across all of their pairs.
+fn keep_top_characters(pairs: Vec<CoOccurrence>, limit: usize) -> Vec<CoOccurrence> {
+    let mut totals: HashMap<&str, u64> = HashMap::default();
+    for pair in pairs.iter() {
+        *totals.entry(&pair.first).or_default() += pair.count;
+        *totals.entry(&pair.second).or_default() += pair.count;
+    }
+    let mut totals: Vec<(&str, u64)> = totals.into_iter().collect();
+    // Most works first, then alphabetically so ties are broken consistently
+    totals.sort_by(|(a_name, a_count), (b_name, b_count)| {
+        b_count.cmp(a_count).then(a_name.cmp(b_name))
+    });
+    let top: HashSet<String> = totals
+        .into_iter()
+        .take(limit)
+        .map(|(name, _count)| name.to_owned())
+        .collect();
+
+    pairs
+        .into_iter()
+        .filter(|pair| top.contains(&pair.first) && top.contains(&pair.second))
+        .collect()
+}
+
+/// Visual tuning of edge weights in the chord diagram.
+///
+/// Any scaling here distorts the proportions between ships, and is only
+/// intended to stop a single dominant ship from hiding the rest.
+#[derive(Debug, Default)]
+struct EdgeScale {
+    /// Maximum value of any single edge.
+    cap: Option<f64>,
+    /// Whether to log-scale edges, after capping.
+    log: bool,
+}
+
+impl EdgeScale {
+    fn apply(&self, value: f64) -> f64 {
+        let value = match self.cap {
+            Some(cap) => value.min(cap),
+            None => value,
+        };
+        if self.log {
+            value.ln_1p()
+        } else {
+            value
+        }
+    }
+}
+
+/// Build a symmetric matrix of co-occurrence counts.
+///
+/// Returns the sorted names of all characters, and the matrix with rows and
+/// columns in the same order.
+fn co_occurrence_matrix(pairs: &[CoOccurrence]) -> (Vec<String>, Vec<Vec<f64>>) {
     // Get unique, sorted list of all characters
     let mut characters: HashSet<&str> = HashSet::default();
-    for (ship, _count) in freqs.iter() {
-        for character in ship.characters.iter() {
-            characters.insert(&character);
-        }
+    for pair in pairs.iter() {
+        characters.insert(&pair.first);
+        characters.insert(&pair.second);
     }
     let mut names: Vec<String> = characters.into_iter().map(ToOwned::to_owned).collect();
     names.sort_unstable();
@@ -129,25 +448,50 @@ fn output_chord(freqs: HashMap<Ship, u64>) {
 
     // Initialize the matrix with zeroes
     let mut matrix: Vec<Vec<f64>> = vec![vec![0.; names.len()]; names.len()];
-    for (ship, count) in freqs.iter() {
+    for pair in pairs.iter() {
         let character_one_index = *character_index
-            .get(&ship.characters[0].as_ref())
+            .get(pair.first.as_str())
             .expect("character to have index");
         let character_two_index = *character_index
-            .get(&ship.characters[1].as_ref())
+            .get(pair.second.as_str())
             .expect("character to have index");
-        matrix[character_one_index][character_two_index] += *count as f64;
-        matrix[character_two_index][character_one_index] += *count as f64;
+        matrix[character_one_index][character_two_index] += pair.count as f64;
+        matrix[character_two_index][character_one_index] += pair.count as f64;
+    }
+    (names, matrix)
+}
+
+/// Write a co-occurrence matrix as CSV, with names as both the header row
+/// and the first column.
+fn write_matrix_csv<W: Write>(writer: W, names: &[String], matrix: &[Vec<f64>]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(std::iter::once("").chain(names.iter().map(String::as_str)))?;
+    for (name, row) in names.iter().zip(matrix.iter()) {
+        writer.write_record(
+            std::iter::once(name.clone()).chain(row.iter().map(ToString::to_string)),
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn output_chord(
+    names: Vec<String>,
+    mut matrix: Vec<Vec<f64>>,
+    edge_scale: &EdgeScale,
+    palette: &ColorPalette,
+) {
+    for row in matrix.iter_mut() {
+        for cell in row.iter_mut() {
+            *cell = edge_scale.apply(*cell);
+        }
     }
 
     // Generate colors for each name
     let colors: Vec<String> = names
         .iter()
         .enumerate()
-        .map(|(index, _name)| {
-            let color = golden_color(index);
-            color.as_hex()
-        })
+        .map(|(index, _name)| palette.color(index).as_hex())
         .collect();
 
     Chord {
@@ -166,59 +510,205 @@ fn output_chord(freqs: HashMap<Ship, u64>) {
 /// Use the golden ratio to deal out differing colors for a large number of items.
 ///
 /// Color hues remain evently distributed across both small and large sets.
-fn golden_color(index: usize) -> LinSrgb<u8> {
-    Hsv::new((index * 360) as f32 / GOLDEN_RATIO, 0.68, 0.69)
+fn golden_color(index: usize, saturation: f32, value: f32) -> LinSrgb<u8> {
+    Hsv::new(golden_hue(index), saturation, value)
         .into_rgb()
         .into_format::<u8>()
 }
 
+/// Hue in degrees, in `0..360`, of the color at `index`.
+///
+/// Each hue steps `360 / GOLDEN_RATIO` degrees around the color wheel from the
+/// last. As the golden ratio is irrational the hues never repeat, and each new
+/// hue falls in one of the largest gaps left by those before it.
+fn golden_hue(index: usize) -> f32 {
+    (index as f32 * 360. / GOLDEN_RATIO) % 360.
+}
+
 trait DisplayHex {
     fn as_hex(&self) -> String;
 }
 
 impl DisplayHex for LinSrgb<u8> {
     fn as_hex(&self) -> String {
-        format!("#{:X}{:X}{:X}", self.red, self.green, self.blue)
+        format!("#{:02X}{:02X}{:02X}", self.red, self.green, self.blue)
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize)]
-struct Ship {
-    characters: Vec<String>,
-    kind: ShipKind,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl FromStr for Ship {
-    type Err = Error;
+    #[test]
+    fn test_edge_scale_cap() {
+        let edge_scale = EdgeScale {
+            cap: Some(100.),
+            log: false,
+        };
+        assert_eq!(edge_scale.apply(50.), 50.);
+        assert_eq!(edge_scale.apply(100.), 100.);
+        assert_eq!(edge_scale.apply(2500.), 100.);
+    }
 
-    /// Given a ship tag, returns a pair of characters in the ship.
-    ///
-    /// The pair of characters will be sorted, to make tag deduplication easier.
-    ///
-    /// This function will return `None` if:
-    ///
-    /// - the ship kind could not be determined
-    fn from_str(ship: &str) -> Result<Self> {
-        let (delimiter, kind) = if ship.contains('/') {
-            ('/', ShipKind::Romantic)
-        } else if ship.contains('&') {
-            ('&', ShipKind::Platonic)
-        } else {
-            return Err(anyhow!("Unknown ship kind in: '{}'", ship));
+    #[test]
+    fn test_edge_scale_log() {
+        let edge_scale = EdgeScale {
+            cap: None,
+            log: true,
+        };
+        assert_eq!(edge_scale.apply(0.), 0.);
+        assert!((edge_scale.apply(99.) - 100f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_edge_scale_cap_then_log() {
+        let edge_scale = EdgeScale {
+            cap: Some(99.),
+            log: true,
+        };
+        assert!((edge_scale.apply(2500.) - 100f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_edge_scale_default() {
+        assert_eq!(EdgeScale::default().apply(2500.), 2500.);
+    }
+
+    #[test]
+    fn test_apply_aliases() {
+        let pair = |first: &str, second: &str, count| CoOccurrence {
+            first: first.to_owned(),
+            second: second.to_owned(),
+            count,
+        };
+        let aliases: HashMap<String, String> = vec![
+            ("Prince Zuko".to_owned(), "Zuko".to_owned()),
+            ("Lee".to_owned(), "Zuko".to_owned()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            apply_aliases(
+                vec![
+                    pair("Katara", "Zuko", 100),
+                    pair("Katara", "Prince Zuko", 20),
+                    pair("Aang", "Prince Zuko", 5),
+                    pair("Lee", "Zuko", 3),
+                ],
+                &aliases
+            ),
+            vec![pair("Aang", "Zuko", 5), pair("Katara", "Zuko", 120)]
+        );
+    }
+
+    #[test]
+    fn test_read_aliases_csv() {
+        let aliases =
+            read_aliases_csv("Prince Zuko,Zuko\n\"Zuko, Banished Prince\",Zuko\n".as_bytes())
+                .unwrap();
+        assert_eq!(aliases.len(), 2);
+        assert_eq!(aliases["Prince Zuko"], "Zuko");
+        assert_eq!(aliases["Zuko, Banished Prince"], "Zuko");
+    }
+
+    #[test]
+    fn test_focus_on() {
+        let pair = |first: &str, second: &str, count| CoOccurrence {
+            first: first.to_owned(),
+            second: second.to_owned(),
+            count,
+        };
+        let pairs = vec![
+            pair("Katara", "Zuko", 100),
+            pair("Aang", "Katara", 30),
+            pair("Sokka", "Suki", 20),
+        ];
+        assert_eq!(
+            focus_on(pairs.clone(), "katara").unwrap(),
+            vec![pair("Katara", "Zuko", 100), pair("Aang", "Katara", 30)]
+        );
+        assert!(focus_on(pairs, "Toph").is_err());
+    }
+
+    #[test]
+    fn test_drop_small_edges() {
+        let pair = |first: &str, second: &str, count| CoOccurrence {
+            first: first.to_owned(),
+            second: second.to_owned(),
+            count,
+        };
+        assert_eq!(
+            drop_small_edges(
+                vec![
+                    pair("Katara", "Zuko", 100),
+                    pair("Aang", "Katara", 10),
+                    pair("Sokka", "Suki", 9),
+                ],
+                10
+            ),
+            vec![pair("Katara", "Zuko", 100), pair("Aang", "Katara", 10)]
+        );
+    }
+
+    #[test]
+    fn test_keep_top_characters() {
+        let pair = |first: &str, second: &str, count| CoOccurrence {
+            first: first.to_owned(),
+            second: second.to_owned(),
+            count,
         };
+        assert_eq!(
+            keep_top_characters(
+                vec![
+                    pair("Katara", "Zuko", 100),
+                    pair("Aang", "Katara", 30),
+                    pair("Sokka", "Suki", 20),
+                    pair("Sokka", "Zuko", 5),
+                ],
+                3
+            ),
+            vec![pair("Katara", "Zuko", 100), pair("Aang", "Katara", 30)]
+        );
+    }
+
+    #[test]
+    fn test_write_matrix_csv() {
+        let pair = |first: &str, second: &str, count| CoOccurrence {
+            first: first.to_owned(),
+            second: second.to_owned(),
+            count,
+        };
+        let (names, matrix) =
+            co_occurrence_matrix(&[pair("Katara", "Zuko", 100), pair("Aang", "Katara", 30)]);
+        let mut buffer = Vec::new();
+        write_matrix_csv(&mut buffer, &names, &matrix).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            ",Aang,Katara,Zuko\nAang,0,30,0\nKatara,30,0,100\nZuko,0,100,0\n"
+        );
+    }
+
+    #[test]
+    fn test_golden_hue() {
+        let hues: Vec<f32> = (0..12).map(golden_hue).collect();
+        for (index, hue) in hues.iter().enumerate() {
+            assert!((0. ..360.).contains(hue), "hue {} out of range", hue);
+            for other in hues[index + 1..].iter() {
+                assert!((hue - other).abs() > 1., "hue {} repeated", hue);
+            }
+        }
+    }
+
+    #[test]
+    fn test_colorblind_palette_cycles() {
+        let palette = ColorPalette::Colorblind;
+        assert_eq!(palette.color(0).as_hex(), "#E69F00");
+        assert_eq!(palette.color(8).as_hex(), "#E69F00");
+        assert_eq!(palette.color(7).as_hex(), "#000000");
+    }
 
-        // Split on separators to get characters
-        let mut characters: Vec<String> = ship
-            .split(delimiter)
-            .map(|mut name| {
-                if let Some(fandom_start) = name.find('(') {
-                    name = &name[..fandom_start];
-                }
-                name.trim().to_owned()
-            })
-            .collect();
-        characters.sort_unstable();
-
-        Ok(Self { characters, kind })
+    #[test]
+    fn test_as_hex_zero_pads_channels() {
+        assert_eq!(LinSrgb::new(15u8, 160, 0).as_hex(), "#0FA000");
     }
 }
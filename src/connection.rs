@@ -0,0 +1,64 @@
+use crate::error::{Context, Result};
+use elasticsearch::{
+    auth::Credentials,
+    cert::{Certificate, CertificateValidation},
+    http::{
+        transport::{SingleNodeConnectionPool, TransportBuilder},
+        Url,
+    },
+    Elasticsearch,
+};
+use std::{fs, path::PathBuf};
+use structopt::StructOpt;
+
+// Command line options for connecting to an elasticsearch cluster.
+//
+// Include in a binary's options with `#[structopt(flatten)]`. Not a doc comment, as
+// structopt would use it as the about text of the binary.
+#[derive(Debug, StructOpt)]
+pub struct ElasticsearchOpt {
+    /// Endpoint of elasticsearch cluster
+    #[structopt(long = "elasticsearch")]
+    pub elasticsearch: String,
+
+    /// Username for basic authentication with the elasticsearch cluster
+    #[structopt(long = "es-username", env = "ES_USERNAME")]
+    pub es_username: Option<String>,
+
+    /// Password for basic authentication with the elasticsearch cluster
+    #[structopt(long = "es-password", env = "ES_PASSWORD", hide_env_values = true)]
+    pub es_password: Option<String>,
+
+    /// Skip validation of the elasticsearch cluster's TLS certificate
+    #[structopt(long = "es-insecure", conflicts_with = "es-ca-cert")]
+    pub es_insecure: bool,
+
+    /// PEM encoded CA certificate to validate the elasticsearch cluster against
+    #[structopt(long = "es-ca-cert")]
+    pub es_ca_cert: Option<PathBuf>,
+}
+
+impl ElasticsearchOpt {
+    /// Build a client for the configured cluster.
+    pub fn client(&self) -> Result<Elasticsearch> {
+        let url = Url::parse(&self.elasticsearch)?;
+        let mut builder = TransportBuilder::new(SingleNodeConnectionPool::new(url));
+        if let Some(username) = &self.es_username {
+            builder = builder.auth(Credentials::Basic(
+                username.to_owned(),
+                self.es_password.clone().unwrap_or_default(),
+            ));
+        }
+        if self.es_insecure {
+            builder = builder.cert_validation(CertificateValidation::None);
+        } else if let Some(path) = &self.es_ca_cert {
+            let pem = fs::read(path).context("CA certificate file")?;
+            builder = builder.cert_validation(CertificateValidation::Full(
+                Certificate::from_pem(&pem).context("CA certificate")?,
+            ));
+        }
+        Ok(Elasticsearch::new(
+            builder.build().map_err(elasticsearch::Error::from)?,
+        ))
+    }
+}
@@ -0,0 +1,134 @@
+use crate::search::TagKind;
+use std::error::Error as StdError;
+use thiserror::Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors returned by the `fandom_data` library.
+///
+/// Messages do not repeat the error they were caused by, so print the whole chain of
+/// sources, such as with `{:#}` once converted to an `anyhow::Error`.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A page or response did not have the expected structure.
+    #[error("Failed to parse {what}")]
+    Parse {
+        what: String,
+        #[source]
+        source: Option<Box<dyn StdError + Send + Sync>>,
+    },
+    /// A request to elasticsearch could not be made.
+    #[error(transparent)]
+    Elasticsearch(#[from] elasticsearch::Error),
+    /// Elasticsearch responded, but not successfully.
+    #[error("Elasticsearch returned {status}: {body}")]
+    ElasticsearchResponse { status: u16, body: String },
+    /// Elasticsearch accepted a bulk request, but failed to index some of its works.
+    #[error("Failed to index {failed} works, such as {id}: {reason}")]
+    BulkIndex {
+        failed: usize,
+        id: String,
+        reason: String,
+    },
+    /// A request to the Archive failed.
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Url(#[from] url::ParseError),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Failed to log in to the Archive as '{0}'")]
+    Login(String),
+    /// A ship tag did not separate its characters with `/` or `&`.
+    #[error("Unknown ship kind in: '{0}'")]
+    UnknownShipKind(String),
+    /// A file's extension did not match any supported format.
+    #[error(
+        "Unknown format of '{0}', expected an extension of: {extensions}",
+        extensions = crate::format::SUPPORTED_EXTENSIONS
+    )]
+    UnknownFormat(String),
+    #[error("Co-occurrence is not supported for tag kind: {0:?}")]
+    UnsupportedTagKind(TagKind),
+    /// A string could not be parsed as one of the library's options.
+    #[error("Invalid {kind}: '{value}'")]
+    InvalidValue { kind: &'static str, value: String },
+}
+
+impl Error {
+    pub(crate) fn invalid_value(kind: &'static str, value: &str) -> Self {
+        Self::InvalidValue {
+            kind,
+            value: value.to_owned(),
+        }
+    }
+}
+
+/// Describe what was being parsed when a value is missing or invalid.
+pub(crate) trait Context<T> {
+    fn context(self, what: &str) -> Result<T>;
+
+    /// As `context`, only building the description on failure.
+    fn with_context<F: FnOnce() -> String>(self, what: F) -> Result<T>;
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context(self, what: &str) -> Result<T> {
+        self.with_context(|| what.to_owned())
+    }
+
+    fn with_context<F: FnOnce() -> String>(self, what: F) -> Result<T> {
+        self.ok_or_else(|| Error::Parse {
+            what: what(),
+            source: None,
+        })
+    }
+}
+
+impl<T, E> Context<T> for Result<T, E>
+where
+    E: StdError + Send + Sync + 'static,
+{
+    fn context(self, what: &str) -> Result<T> {
+        self.with_context(|| what.to_owned())
+    }
+
+    fn with_context<F: FnOnce() -> String>(self, what: F) -> Result<T> {
+        self.map_err(|error| Error::Parse {
+            what: what(),
+            source: Some(Box::new(error)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_context() {
+        let error = "x".parse::<u32>().context("number").unwrap_err();
+        assert_eq!(error.to_string(), "Failed to parse number");
+        assert_eq!(
+            error.source().unwrap().to_string(),
+            "invalid digit found in string"
+        );
+        assert!(None::<u32>
+            .context("number")
+            .unwrap_err()
+            .source()
+            .is_none());
+    }
+
+    #[test]
+    fn test_context_chain() {
+        let error = anyhow::Error::from("x".parse::<u32>().context("number").unwrap_err());
+        assert_eq!(
+            format!("{:#}", error),
+            "Failed to parse number: invalid digit found in string"
+        );
+    }
+}
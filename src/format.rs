@@ -0,0 +1,178 @@
+use crate::error::{Error, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Stdout, Write},
+    path::Path,
+};
+
+/// Extensions of the supported formats, for error messages.
+pub const SUPPORTED_EXTENSIONS: &str =
+    ".jsonl, .json, .csv, .parquet, .jsonl.gz, .json.gz, .csv.gz";
+
+/// Format of a file of works, detected from its extension.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    /// One JSON work per line.
+    Jsonl,
+    JsonlGz,
+    /// The columns written by `Work::write_csv_header`.
+    Csv,
+    CsvGz,
+    Parquet,
+}
+
+impl Format {
+    /// Detect the format of `path` from its extension, where `.gz` is gzipped.
+    ///
+    /// `.json` files are read as one work per line, the same as `.jsonl`.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let extension = |path: &Path| {
+            path.extension()
+                .and_then(OsStr::to_str)
+                .map(str::to_lowercase)
+        };
+        let unknown = || Error::UnknownFormat(path.display().to_string());
+        match extension(path).as_deref() {
+            Some("jsonl") | Some("json") => Ok(Self::Jsonl),
+            Some("csv") => Ok(Self::Csv),
+            Some("parquet") => Ok(Self::Parquet),
+            Some("gz") => match extension(&path.with_extension("")).as_deref() {
+                Some("jsonl") | Some("json") => Ok(Self::JsonlGz),
+                Some("csv") => Ok(Self::CsvGz),
+                _ => Err(unknown()),
+            },
+            _ => Err(unknown()),
+        }
+    }
+
+    pub fn is_gzip(&self) -> bool {
+        matches!(self, Self::JsonlGz | Self::CsvGz)
+    }
+
+    pub fn is_jsonl(&self) -> bool {
+        matches!(self, Self::Jsonl | Self::JsonlGz)
+    }
+
+    pub fn is_csv(&self) -> bool {
+        matches!(self, Self::Csv | Self::CsvGz)
+    }
+
+    /// Open `path` for reading, gunzipping it if this format is gzipped.
+    pub fn open(&self, path: &Path) -> Result<Box<dyn BufRead>> {
+        let file = File::open(path)?;
+        Ok(if self.is_gzip() {
+            Box::new(BufReader::new(GzDecoder::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        })
+    }
+
+    /// Create `path` for writing, gzipping it if this format is gzipped.
+    ///
+    /// Output is only complete once `Output::finish` is called.
+    pub fn create(&self, path: &Path) -> Result<Output> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(if self.is_gzip() {
+            Output::Gzip(GzEncoder::new(file, Compression::default()))
+        } else {
+            Output::File(file)
+        })
+    }
+}
+
+/// Destination to write works to, which must be finished to report any error
+/// writing the end of the output.
+#[derive(Debug)]
+pub enum Output {
+    Stdout(Stdout),
+    File(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Output {
+    pub fn stdout() -> Self {
+        Self::Stdout(io::stdout())
+    }
+
+    /// Flush all output, finishing any gzip stream.
+    pub fn finish(self) -> Result<()> {
+        match self {
+            Self::Stdout(mut stdout) => stdout.flush()?,
+            Self::File(mut file) => file.flush()?,
+            Self::Gzip(encoder) => encoder.finish()?.flush()?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Stdout(stdout) => stdout.write(buf),
+            Self::File(file) => file.write(buf),
+            Self::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout(stdout) => stdout.flush(),
+            Self::File(file) => file.flush(),
+            Self::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn format(path: &str) -> Result<Format> {
+        Format::from_path(Path::new(path))
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(format("works.jsonl").unwrap(), Format::Jsonl);
+        assert_eq!(format("works.json").unwrap(), Format::Jsonl);
+        assert_eq!(format("dir.d/works.JSONL.gz").unwrap(), Format::JsonlGz);
+        assert_eq!(format("works.csv").unwrap(), Format::Csv);
+        assert_eq!(format("works.csv.gz").unwrap(), Format::CsvGz);
+        assert_eq!(format("works.parquet").unwrap(), Format::Parquet);
+    }
+
+    #[test]
+    fn test_create_gzip_finish() {
+        let path =
+            std::env::temp_dir().join(format!("fandom-data-{}.jsonl.gz", std::process::id()));
+        let mut output = Format::JsonlGz.create(&path).unwrap();
+        output.write_all(b"{}\n").unwrap();
+        output.finish().unwrap();
+        let lines: Vec<String> = Format::JsonlGz
+            .open(&path)
+            .unwrap()
+            .lines()
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines, vec!["{}".to_owned()]);
+    }
+
+    #[test]
+    fn test_format_from_path_unknown() {
+        for path in &["works", "works.txt", "works.gz", "works.parquet.gz"] {
+            let error = format(path).unwrap_err();
+            assert!(matches!(error, Error::UnknownFormat(_)), "{:?}", error);
+        }
+        assert_eq!(
+            format("works.txt").unwrap_err().to_string(),
+            format!(
+                "Unknown format of 'works.txt', expected an extension of: {}",
+                SUPPORTED_EXTENSIONS
+            )
+        );
+    }
+}
@@ -1,2 +1,9 @@
+pub mod connection;
+pub mod error;
+pub mod format;
 pub mod scrape;
 pub mod search;
+pub mod ship;
+
+pub use error::{Error, Result};
+pub use scrape::Work;
@@ -1,41 +1,292 @@
-use anyhow::{Context, Result};
+use crate::error::{Context, Error, Result};
 use chrono::NaiveDate;
 use once_cell::sync::Lazy;
-use scraper::{Html, Selector};
-use serde::{Deserialize, Serialize};
+use rand::Rng;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    fmt,
+    io::{Read, Write},
+    str::FromStr,
+    thread::sleep,
+    time::Duration,
+};
+use structopt::StructOpt;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Work {
     pub id: String,
     pub title: String,
-    pub author: Option<String>,
+    /// Pseuds of the creators of the work, which is empty for anonymous works.
+    #[serde(alias = "author", deserialize_with = "deserialize_authors")]
+    pub authors: Vec<String>,
     pub relationships: Vec<String>,
     pub characters: Vec<String>,
     pub freeforms: Vec<String>,
+    /// Whether the listing showed only some of the work's tags, so the full work page has more.
+    #[serde(default)]
+    pub tags_truncated: bool,
+    /// Date shown in search listings, which is when the work was last revised, or when
+    /// it was published for works scraped from full work pages.
     pub date: NaiveDate,
+    /// Date the work was last updated or completed, if it was shown apart from `date`.
+    #[serde(default)]
+    pub updated: Option<NaiveDate>,
     pub language: String,
     pub words: u32,
+    /// Number of chapters posted so far, or 0 if it was not scraped.
+    #[serde(default)]
+    pub chapters: u32,
     pub kudos: u32,
     pub hits: u32,
+    /// Absent from works scraped before comments were.
+    #[serde(default)]
+    pub comments: u32,
+    /// Absent from works scraped before bookmarks were.
+    #[serde(default)]
+    pub bookmarks: u32,
+    /// Summary written by the creators, only scraped from full work pages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Links to each tag, only scraped on request with `search_page_tag_hrefs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag_hrefs: Option<TagHrefs>,
 }
 
-static SELECTOR_WORK: Lazy<Selector> = Lazy::new(|| Selector::parse("li.work").unwrap());
-static SELECTOR_TITLE_AUTHOR: Lazy<Selector> =
-    Lazy::new(|| Selector::parse("h4.heading > a").unwrap());
-static SELECTOR_RELATIONSHIP: Lazy<Selector> =
-    Lazy::new(|| Selector::parse("li.relationships > a.tag").unwrap());
-static SELECTOR_CHARACTER: Lazy<Selector> =
-    Lazy::new(|| Selector::parse("li.characters > a.tag").unwrap());
-static SELECTOR_FREEFORM: Lazy<Selector> =
-    Lazy::new(|| Selector::parse("li.freeforms > a.tag").unwrap());
-static SELECTOR_DATE: Lazy<Selector> = Lazy::new(|| Selector::parse("p.datetime").unwrap());
-static SELECTOR_LANGUAGE: Lazy<Selector> =
-    Lazy::new(|| Selector::parse("dl.stats > dd.language").unwrap());
-static SELECTOR_WORDS: Lazy<Selector> =
-    Lazy::new(|| Selector::parse("dl.stats > dd.words").unwrap());
-static SELECTOR_KUDOS: Lazy<Selector> =
-    Lazy::new(|| Selector::parse("dl.stats > dd.kudos").unwrap());
-static SELECTOR_HITS: Lazy<Selector> = Lazy::new(|| Selector::parse("dl.stats > dd.hits").unwrap());
+/// Read authors as a list, or as the single optional `author` of works saved before
+/// every author was scraped.
+fn deserialize_authors<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Authors {
+        Many(Vec<String>),
+        One(Option<String>),
+    }
+
+    Ok(match Authors::deserialize(deserializer)? {
+        Authors::Many(authors) => authors,
+        Authors::One(author) => author.into_iter().collect(),
+    })
+}
+
+/// Links to the Archive's page for each tag of a work, in the same order as the tag names.
+///
+/// Links follow the Archive's tag wrangling, so are more stable than the names shown.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct TagHrefs {
+    pub relationships: Vec<String>,
+    pub characters: Vec<String>,
+    pub freeforms: Vec<String>,
+}
+
+/// Separator used to flatten list fields into a single CSV column.
+const CSV_LIST_SEPARATOR: &str = "|";
+
+/// A row written by `Work::write_csv_record`, with list fields still joined.
+#[derive(Debug, Deserialize)]
+struct CsvWork {
+    id: String,
+    title: String,
+    authors: String,
+    relationships: String,
+    characters: String,
+    freeforms: String,
+    date: NaiveDate,
+    updated: Option<NaiveDate>,
+    language: String,
+    words: u32,
+    /// Absent from CSV written before chapters were scraped.
+    #[serde(default)]
+    chapters: u32,
+    kudos: u32,
+    hits: u32,
+    /// Absent from CSV written before comments and bookmarks were scraped.
+    #[serde(default)]
+    comments: u32,
+    #[serde(default)]
+    bookmarks: u32,
+}
+
+/// Split a list field joined by `CSV_LIST_SEPARATOR`, where an empty field is an empty list.
+fn split_csv_list(field: &str) -> Vec<String> {
+    if field.is_empty() {
+        return Vec::new();
+    }
+    field
+        .split(CSV_LIST_SEPARATOR)
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+impl From<CsvWork> for Work {
+    fn from(row: CsvWork) -> Self {
+        Self {
+            id: row.id,
+            title: row.title,
+            authors: split_csv_list(&row.authors),
+            relationships: split_csv_list(&row.relationships),
+            characters: split_csv_list(&row.characters),
+            freeforms: split_csv_list(&row.freeforms),
+            tags_truncated: false,
+            date: row.date,
+            updated: row.updated,
+            language: row.language,
+            words: row.words,
+            chapters: row.chapters,
+            kudos: row.kudos,
+            hits: row.hits,
+            comments: row.comments,
+            bookmarks: row.bookmarks,
+            summary: None,
+            tag_hrefs: None,
+        }
+    }
+}
+
+impl Work {
+    /// Link to this work on the Archive at `endpoint`, such as `ENDPOINT_AO3`.
+    pub fn url(&self, endpoint: &str) -> String {
+        format!("{}/works/{}", endpoint, self.id)
+    }
+
+    /// Kudos per hit, a rough measure of how well liked a work is by its readers.
+    ///
+    /// Returns `None` for works with no hits.
+    pub fn kudos_hits_ratio(&self) -> Option<f64> {
+        if self.hits == 0 {
+            return None;
+        }
+        Some(f64::from(self.kudos) / f64::from(self.hits))
+    }
+
+    /// Average words in each chapter posted, a rough measure of pacing.
+    ///
+    /// Returns `None` for works with no chapters scraped.
+    pub fn words_per_chapter(&self) -> Option<f64> {
+        if self.chapters == 0 {
+            return None;
+        }
+        Some(f64::from(self.words) / f64::from(self.chapters))
+    }
+
+    /// Write the CSV header row matching `write_csv_record`.
+    pub fn write_csv_header<W: Write>(writer: &mut csv::Writer<W>) -> Result<()> {
+        writer.write_record([
+            "id",
+            "title",
+            "authors",
+            "relationships",
+            "characters",
+            "freeforms",
+            "date",
+            "updated",
+            "language",
+            "words",
+            "chapters",
+            "kudos",
+            "hits",
+            "comments",
+            "bookmarks",
+        ])?;
+        Ok(())
+    }
+
+    /// Read works from CSV with the header written by `write_csv_header`.
+    ///
+    /// Fields the CSV does not include are left empty.
+    pub fn read_csv<R: Read>(reader: R) -> impl Iterator<Item = Result<Work>> {
+        csv::Reader::from_reader(reader)
+            .into_deserialize::<CsvWork>()
+            .map(|row| Ok(row?.into()))
+    }
+
+    /// Write this work as a CSV row, with list fields joined by `|`.
+    pub fn write_csv_record<W: Write>(&self, writer: &mut csv::Writer<W>) -> Result<()> {
+        writer.write_record(&[
+            self.id.clone(),
+            self.title.clone(),
+            self.authors.join(CSV_LIST_SEPARATOR),
+            self.relationships.join(CSV_LIST_SEPARATOR),
+            self.characters.join(CSV_LIST_SEPARATOR),
+            self.freeforms.join(CSV_LIST_SEPARATOR),
+            self.date.to_string(),
+            self.updated
+                .map(|date| date.to_string())
+                .unwrap_or_default(),
+            self.language.clone(),
+            self.words.to_string(),
+            self.chapters.to_string(),
+            self.kudos.to_string(),
+            self.hits.to_string(),
+            self.comments.to_string(),
+            self.bookmarks.to_string(),
+        ])?;
+        Ok(())
+    }
+}
+
+/// CSS selectors used to find each part of a work on a search page.
+///
+/// The `Default` matches the Archive's current markup. If the markup changes,
+/// override only the affected selectors.
+#[derive(Debug, Clone)]
+pub struct Selectors {
+    pub work: Selector,
+    pub title: Selector,
+    pub authors: Selector,
+    pub relationship: Selector,
+    pub character: Selector,
+    pub freeform: Selector,
+    pub more_tags: Selector,
+    pub date: Selector,
+    pub updated: Selector,
+    pub language: Selector,
+    pub words: Selector,
+    pub chapters: Selector,
+    pub kudos: Selector,
+    pub hits: Selector,
+    pub comments: Selector,
+    pub bookmarks: Selector,
+    pub pagination: Selector,
+    pub bookmark: Selector,
+}
+
+impl Default for Selectors {
+    fn default() -> Self {
+        Self {
+            work: Selector::parse("li.work").unwrap(),
+            title: Selector::parse("h4.heading > a").unwrap(),
+            // Only links to pseuds, as anonymous works have no author link and
+            // gifted works also link to their recipients
+            authors: Selector::parse("h4.heading > a[rel=author]").unwrap(),
+            relationship: Selector::parse("li.relationships > a.tag").unwrap(),
+            character: Selector::parse("li.characters > a.tag").unwrap(),
+            freeform: Selector::parse("li.freeforms > a.tag").unwrap(),
+            // Link shown in place of the rest of the tags, when a listing has too many to show
+            more_tags: Selector::parse("ul.tags > li.more").unwrap(),
+            date: Selector::parse("p.datetime").unwrap(),
+            // Only shown on work pages with more than one chapter, not in search listings
+            updated: Selector::parse("dl.stats > dd.status").unwrap(),
+            language: Selector::parse("dl.stats > dd.language").unwrap(),
+            words: Selector::parse("dl.stats > dd.words").unwrap(),
+            chapters: Selector::parse("dl.stats > dd.chapters").unwrap(),
+            kudos: Selector::parse("dl.stats > dd.kudos").unwrap(),
+            hits: Selector::parse("dl.stats > dd.hits").unwrap(),
+            comments: Selector::parse("dl.stats > dd.comments").unwrap(),
+            bookmarks: Selector::parse("dl.stats > dd.bookmarks").unwrap(),
+            pagination: Selector::parse("ol.pagination > li").unwrap(),
+            bookmark: Selector::parse("li.bookmark").unwrap(),
+        }
+    }
+}
+
+static DEFAULT_SELECTORS: Lazy<Selectors> = Lazy::new(Selectors::default);
 
 trait SelectExt {
     fn next_text(&mut self) -> Result<&str>;
@@ -55,10 +306,7 @@ impl<'a, 'b> SelectExt for scraper::element_ref::Select<'a, 'b> {
     }
 
     fn next_number(&mut self) -> Result<u32> {
-        self.next_text()?
-            .replace(",", "")
-            .parse()
-            .context("failed to parse number")
+        self.next_text()?.replace(",", "").parse().context("number")
     }
 
     fn collect_texts(&mut self) -> Result<Vec<String>> {
@@ -74,82 +322,858 @@ impl<'a, 'b> SelectExt for scraper::element_ref::Select<'a, 'b> {
 }
 
 pub fn search_page_to_works(body: &str) -> Result<Vec<Work>> {
+    search_page_to_works_with(body, &DEFAULT_SELECTORS)
+}
+
+/// As `search_page_to_works`, using the given selectors.
+pub fn search_page_to_works_with(body: &str, selectors: &Selectors) -> Result<Vec<Work>> {
     let fragment = Html::parse_document(&body);
-    Ok(fragment
-        .select(&*SELECTOR_WORK)
-        .map(|work_element| {
-            let id = work_element
-                .value()
-                .attr("id")
-                .context("work to have id")?
-                .strip_prefix("work_")
-                .context("work id to have prefix")?
-                .to_owned();
-
-            let mut title_author = work_element.select(&*SELECTOR_TITLE_AUTHOR);
-            let title = title_author.next_text().context("title")?.to_owned();
-            let author = title_author.next_text().ok().map(ToOwned::to_owned);
-
-            let relationships = work_element
-                .select(&*SELECTOR_RELATIONSHIP)
-                .collect_texts()
-                .context("relationships")?;
-            let characters = work_element
-                .select(&*SELECTOR_CHARACTER)
-                .collect_texts()
-                .context("characters")?;
-            let freeforms = work_element
-                .select(&*SELECTOR_FREEFORM)
-                .collect_texts()
-                .context("freeforms")?;
-            let date = NaiveDate::parse_from_str(
-                work_element
-                    .select(&*SELECTOR_DATE)
-                    .next_text()
-                    .context("date")?,
-                "%d %b %Y",
+    fragment
+        .select(&selectors.work)
+        .map(|work_element| work_from_element_with(&work_element, selectors))
+        .collect()
+}
+
+/// Text the Archive shows in place of works when a search has no results.
+const NO_RESULTS_TEXT: &str = "No results found";
+
+/// Heading above the results, showing either their count or `NO_RESULTS_TEXT`.
+static RESULTS_HEADING: Lazy<Selector> = Lazy::new(|| Selector::parse("#main h3.heading").unwrap());
+
+/// Pagination details of a search page.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct SearchPageInfo {
+    /// Total number of pages of results, if the page shows pagination.
+    pub total_pages: Option<u32>,
+    /// Whether the Archive reported that there are no results.
+    pub no_results: bool,
+}
+
+impl SearchPageInfo {
+    /// Whether there are no pages of results after `page_number`.
+    pub fn is_last_page(&self, page_number: u32) -> bool {
+        self.no_results
+            || self
+                .total_pages
+                .is_some_and(|total_pages| page_number >= total_pages)
+    }
+}
+
+/// Parse the pagination details of a search page.
+pub fn search_page_info(body: &str) -> SearchPageInfo {
+    let fragment = Html::parse_document(body);
+    // The highest numbered page link, as links to pages in between are elided
+    let total_pages = fragment
+        .select(&DEFAULT_SELECTORS.pagination)
+        .filter_map(|element| element.text().collect::<String>().trim().parse().ok())
+        .max();
+    SearchPageInfo {
+        total_pages,
+        no_results: fragment
+            .select(&RESULTS_HEADING)
+            .flat_map(|element| element.text())
+            .any(|text| text.contains(NO_RESULTS_TEXT)),
+    }
+}
+
+/// A work on a search page which could not be parsed.
+#[derive(Debug)]
+pub struct WorkParseError {
+    /// Id of the work, if it could be found.
+    pub id: Option<String>,
+    pub error: Error,
+}
+
+impl fmt::Display for WorkParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.id {
+            Some(id) => write!(f, "Failed to parse work {}: {}", id, self.error)?,
+            None => write!(f, "Failed to parse work: {}", self.error)?,
+        }
+        let mut source = self.error.source();
+        while let Some(error) = source {
+            write!(f, ": {}", error)?;
+            source = error.source();
+        }
+        Ok(())
+    }
+}
+
+impl StdError for WorkParseError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// As `search_page_to_works`, but skipping any works which fail to parse.
+///
+/// Returns the works which were parsed, and an error for each which was not.
+pub fn search_page_to_works_lenient(body: &str) -> (Vec<Work>, Vec<WorkParseError>) {
+    search_page_to_works_lenient_with(body, &DEFAULT_SELECTORS)
+}
+
+/// As `search_page_to_works_lenient`, using the given selectors.
+pub fn search_page_to_works_lenient_with(
+    body: &str,
+    selectors: &Selectors,
+) -> (Vec<Work>, Vec<WorkParseError>) {
+    let fragment = Html::parse_document(body);
+    let mut works = Vec::new();
+    let mut errors = Vec::new();
+    for work_element in fragment.select(&selectors.work) {
+        match work_from_element_with(&work_element, selectors) {
+            Ok(work) => works.push(work),
+            Err(error) => errors.push(WorkParseError {
+                id: work_element
+                    .value()
+                    .attr("id")
+                    .and_then(|id| id.strip_prefix("work_"))
+                    .map(ToOwned::to_owned),
+                error,
+            }),
+        }
+    }
+    (works, errors)
+}
+
+/// Formats the Archive has shown dates in, tried in order.
+const DATE_FORMATS: &[&str] = &["%d %b %Y", "%Y-%m-%d", "%d %B %Y", "%b %d, %Y"];
+
+/// Parse a date shown on a search page, in any of `DATE_FORMATS`.
+fn parse_date(text: &str) -> Result<NaiveDate> {
+    let text = text.trim();
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(text, format).ok())
+        .with_context(|| {
+            format!(
+                "date '{}' in any format of: {}",
+                text,
+                DATE_FORMATS.join(", ")
             )
-            .expect("unexpected date format");
-            let language = work_element
-                .select(&*SELECTOR_LANGUAGE)
-                .next_text()
-                .unwrap_or("")
-                .to_owned();
-            let words = work_element
-                .select(&*SELECTOR_WORDS)
-                .next_number()
-                .unwrap_or(0);
-            let kudos = work_element
-                .select(&*SELECTOR_KUDOS)
-                .next_number()
-                .unwrap_or(0);
-            let hits = work_element
-                .select(&*SELECTOR_HITS)
-                .next_number()
-                .unwrap_or(0);
-
-            Ok(Work {
-                id,
-                title,
-                author,
-                relationships,
-                characters,
-                freeforms,
-                date,
-                language,
-                words,
-                kudos,
-                hits,
-            })
         })
-        .collect::<Result<_>>()?)
+}
+
+/// Parse a single work from its `li.work` element on a search page.
+pub fn work_from_element(work_element: &ElementRef) -> Result<Work> {
+    work_from_element_with(work_element, &DEFAULT_SELECTORS)
+}
+
+/// As `work_from_element`, using the given selectors.
+pub fn work_from_element_with(work_element: &ElementRef, selectors: &Selectors) -> Result<Work> {
+    let id = work_element
+        .value()
+        .attr("id")
+        .context("work to have id")?
+        .strip_prefix("work_")
+        .context("work id to have prefix")?
+        .to_owned();
+    work_with_id_from_element(work_element, id, selectors)
+}
+
+/// Parse the parts of a work other than its id, which is found differently on each kind of page.
+fn work_with_id_from_element(
+    work_element: &ElementRef,
+    id: String,
+    selectors: &Selectors,
+) -> Result<Work> {
+    let title = work_element
+        .select(&selectors.title)
+        .next_text()
+        .context("title")?
+        .to_owned();
+    let authors = work_element
+        .select(&selectors.authors)
+        .collect_texts()
+        .context("authors")?;
+
+    let relationships = work_element
+        .select(&selectors.relationship)
+        .collect_texts()
+        .context("relationships")?;
+    let characters = work_element
+        .select(&selectors.character)
+        .collect_texts()
+        .context("characters")?;
+    let freeforms = work_element
+        .select(&selectors.freeform)
+        .collect_texts()
+        .context("freeforms")?;
+    let tags_truncated = work_element.select(&selectors.more_tags).next().is_some();
+    let date = parse_date(
+        work_element
+            .select(&selectors.date)
+            .next_text()
+            .context("date")?,
+    )?;
+    let updated = match work_element.select(&selectors.updated).next_text() {
+        Ok(updated) => Some(parse_date(updated)?),
+        Err(_) => None,
+    };
+    let language = work_element
+        .select(&selectors.language)
+        .next_text()
+        .unwrap_or("")
+        .to_owned();
+    let words = work_element
+        .select(&selectors.words)
+        .next_number()
+        .unwrap_or(0);
+    // Shown as posted/expected, such as `2/?` for a work in progress
+    let chapters = match work_element.select(&selectors.chapters).next_text() {
+        Ok(chapters) => chapters
+            .split('/')
+            .next()
+            .unwrap_or("")
+            .replace(",", "")
+            .parse()
+            .context("chapters")?,
+        Err(_) => 0,
+    };
+    let kudos = work_element
+        .select(&selectors.kudos)
+        .next_number()
+        .unwrap_or(0);
+    let hits = work_element
+        .select(&selectors.hits)
+        .next_number()
+        .unwrap_or(0);
+    // Only shown once a work has any
+    let comments = work_element
+        .select(&selectors.comments)
+        .next_number()
+        .unwrap_or(0);
+    let bookmarks = work_element
+        .select(&selectors.bookmarks)
+        .next_number()
+        .unwrap_or(0);
+
+    Ok(Work {
+        id,
+        title,
+        authors,
+        relationships,
+        characters,
+        freeforms,
+        tags_truncated,
+        date,
+        updated,
+        language,
+        words,
+        chapters,
+        kudos,
+        hits,
+        comments,
+        bookmarks,
+        summary: None,
+        tag_hrefs: None,
+    })
+}
+
+/// Selectors for the parts of a full work page which differ from a search page.
+static WORK_PAGE_SELECTORS: Lazy<Selectors> = Lazy::new(|| Selectors {
+    title: Selector::parse("h2.title").unwrap(),
+    authors: Selector::parse("h3.byline > a[rel=author]").unwrap(),
+    relationship: Selector::parse("dd.relationship a.tag").unwrap(),
+    character: Selector::parse("dd.character a.tag").unwrap(),
+    freeform: Selector::parse("dd.freeform a.tag").unwrap(),
+    date: Selector::parse("dl.stats > dd.published").unwrap(),
+    language: Selector::parse("dl.meta > dd.language").unwrap(),
+    ..Selectors::default()
+});
+
+static WORK_PAGE_ID: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("#kudo_commentable_id").unwrap());
+
+static WORK_PAGE_SUMMARY: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("div.summary > blockquote.userstuff > p").unwrap());
+
+/// Parse a full work page, such as `/works/{id}`.
+///
+/// Unlike search listings, the full page shows every tag of the work, and its summary.
+pub fn work_page_to_work(body: &str) -> Result<Work> {
+    let document = Html::parse_document(body);
+    let id = document
+        .select(&WORK_PAGE_ID)
+        .next()
+        .and_then(|element| element.value().attr("value"))
+        .context("work page to have id")?
+        .to_owned();
+    let mut work = work_with_id_from_element(&document.root_element(), id, &WORK_PAGE_SELECTORS)?;
+    work.title = work.title.trim().to_owned();
+    let paragraphs: Vec<String> = document
+        .select(&WORK_PAGE_SUMMARY)
+        .map(|paragraph| paragraph.text().collect::<String>().trim().to_owned())
+        .collect();
+    if !paragraphs.is_empty() {
+        work.summary = Some(paragraphs.join("\n\n"));
+    }
+    Ok(work)
+}
+
+/// Parse the works on a page of bookmarks, such as a user's bookmarks.
+///
+/// Bookmarks of series and external works are skipped, as are bookmarks of works which
+/// have been deleted or are restricted, which have no title link.
+pub fn bookmarks_page_to_works(body: &str) -> Result<Vec<Work>> {
+    let selectors = &*DEFAULT_SELECTORS;
+    let fragment = Html::parse_document(body);
+    let mut works = Vec::new();
+    for bookmark_element in fragment.select(&selectors.bookmark) {
+        // The bookmark element's id is that of the bookmark, so take the work's from its link
+        let href = bookmark_element
+            .select(&selectors.title)
+            .next()
+            .and_then(|title| title.value().attr("href"));
+        let id = match href.and_then(|href| href.strip_prefix("/works/")) {
+            Some(id) => id.to_owned(),
+            None => continue,
+        };
+        works.push(work_with_id_from_element(&bookmark_element, id, selectors)?);
+    }
+    Ok(works)
+}
+
+/// Url of a page of a user's bookmarks on the Archive at `endpoint`.
+pub fn bookmarks_url(endpoint: &str, user: &str, number: u32) -> Result<Url> {
+    let mut url = Url::parse(endpoint)?;
+    url.path_segments_mut()
+        .map_err(|_| Error::invalid_value("endpoint", endpoint))?
+        .extend(&["users", user, "bookmarks"]);
+    url.query_pairs_mut()
+        .append_pair("page", &number.to_string());
+    Ok(url)
+}
+
+/// Get the tag links of each work on a search page, by work id.
+///
+/// Set these on works from `search_page_to_works` to include them in the output.
+pub fn search_page_tag_hrefs(body: &str) -> HashMap<String, TagHrefs> {
+    let hrefs = |work_element: &ElementRef, selector: &Selector| -> Vec<String> {
+        work_element
+            .select(selector)
+            .filter_map(|element| element.value().attr("href"))
+            .map(ToOwned::to_owned)
+            .collect()
+    };
+    Html::parse_document(body)
+        .select(&DEFAULT_SELECTORS.work)
+        .filter_map(|work_element| {
+            let id = work_element.value().attr("id")?.strip_prefix("work_")?;
+            Some((
+                id.to_owned(),
+                TagHrefs {
+                    relationships: hrefs(&work_element, &DEFAULT_SELECTORS.relationship),
+                    characters: hrefs(&work_element, &DEFAULT_SELECTORS.character),
+                    freeforms: hrefs(&work_element, &DEFAULT_SELECTORS.freeform),
+                },
+            ))
+        })
+        .collect()
 }
 
 pub const ENDPOINT_AO3: &str = "https://archiveofourown.org";
 
+/// User agent to identify requests to the Archive by, with a way to get in touch.
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    "fandom-data/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/tommilligan/fandom-data)"
+);
+
+static SELECTOR_AUTHENTICITY_TOKEN: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("form#new_user input[name=authenticity_token]").unwrap());
+
+/// Get the form token from the Archive's login page, which must be sent back when logging in.
+fn authenticity_token(body: &str) -> Result<String> {
+    Html::parse_document(body)
+        .select(&SELECTOR_AUTHENTICITY_TOKEN)
+        .next()
+        .and_then(|element| element.value().attr("value"))
+        .map(ToOwned::to_owned)
+        .context("login form to have authenticity token")
+}
+
+/// Log in to the Archive at `endpoint`, so works restricted to registered users are listed.
+///
+/// The `client` must have a cookie store enabled, to keep the session for later requests.
+pub fn login(
+    client: &reqwest::blocking::Client,
+    endpoint: &str,
+    username: &str,
+    password: &str,
+) -> Result<()> {
+    let login_url = format!("{}/users/login", endpoint);
+    let token = authenticity_token(&client.get(&login_url).send()?.text()?)?;
+    let response = client
+        .post(&login_url)
+        .form(&[
+            ("authenticity_token", token.as_str()),
+            ("user[login]", username),
+            ("user[password]", password),
+            ("commit", "Log in"),
+        ])
+        .send()?
+        .error_for_status()?;
+    // A successful login redirects away from the login page
+    if response.url().path() == "/users/login" {
+        return Err(Error::Login(username.to_owned()));
+    }
+    Ok(())
+}
+
+/// Delay before the first retry of a failed request, doubled for each later retry.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// Fetch the body of a page, retrying up to `max_retries` times if the request fails transiently.
+///
+/// Connection errors, timeouts and server errors are retried with exponential backoff and
+/// jitter. Any other error, such as a 404, fails immediately.
+///
+/// `before_attempt` is called before the first attempt and every retry, such as to wait for
+/// a rate limit, so retries are throttled the same as other requests.
+pub fn fetch_page_with_retry(
+    client: &reqwest::blocking::Client,
+    url: Url,
+    max_retries: u32,
+    before_attempt: impl Fn(),
+) -> Result<String> {
+    let mut attempt = 0;
+    loop {
+        before_attempt();
+        let result = client
+            .get(url.clone())
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text());
+        match result {
+            Err(error) if attempt < max_retries && is_retryable(&error) => {
+                let delay = retry_delay(attempt);
+                log::warn!(
+                    "Retrying {} in {:.1}s after error: {}",
+                    url,
+                    delay.as_secs_f64(),
+                    error
+                );
+                sleep(delay);
+                attempt += 1;
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+fn is_retryable(error: &reqwest::Error) -> bool {
+    error.is_timeout()
+        || error.is_request()
+        || error
+            .status()
+            .is_some_and(|status| status.is_server_error())
+}
+
+/// Backoff before retry number `attempt` (from zero), with up to the same again added at random.
+fn retry_delay(attempt: u32) -> Duration {
+    let backoff = RETRY_BASE_DELAY * 2u32.saturating_pow(attempt);
+    backoff + backoff.mul_f64(rand::thread_rng().gen_range(0.0..1.0))
+}
+
 /// Get pages from the beginning of time onwards.
-pub fn page_url(endpoint: &str, number: u32) -> String {
-    format!("{}/works/search?commit=Search&page={}&utf8=✓&work_search[bookmarks_count]=&work_search[character_names]=&work_search[comments_count]=&work_search[complete]=&work_search[creators]=&work_search[crossover]=&work_search[fandom_names]=Avatar: The Last Airbender&work_search[freeform_names]=&work_search[hits]=&work_search[kudos_count]=&work_search[language_id]=&work_search[query]=&work_search[rating_ids]=&work_search[relationship_names]=&work_search[revised_at]=&work_search[single_chapter]=0&work_search[sort_column]=created_at&work_search[sort_direction]=asc&work_search[title]=&work_search[word_count]", endpoint, number)
+///
+/// A thin wrapper around `PageUrlParams`, which should be preferred.
+#[allow(clippy::too_many_arguments)]
+pub fn page_url(
+    endpoint: &str,
+    number: u32,
+    fandom: &str,
+    creators: Option<&str>,
+    language: Option<&str>,
+    crossover: Option<bool>,
+    sort_column: &SortColumn,
+    sort_direction: &SortDirection,
+) -> Result<Url> {
+    PageUrlParams {
+        fandom: fandom.to_owned(),
+        creators: creators.map(ToOwned::to_owned),
+        language: language.map(ToOwned::to_owned),
+        crossover,
+        rating: None,
+        sort_column: sort_column.clone(),
+        sort_direction: sort_direction.clone(),
+    }
+    .build(endpoint, number)
+}
+
+/// Filters and ordering for a page of AO3 search results.
+///
+/// Unset filters match all works.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct PageUrlParams {
+    fandom: String,
+    creators: Option<String>,
+    language: Option<String>,
+    crossover: Option<bool>,
+    rating: Option<Rating>,
+    sort_column: SortColumn,
+    sort_direction: SortDirection,
+}
+
+impl PageUrlParams {
+    /// Fandom to search within. May contain any characters.
+    pub fn fandom(mut self, fandom: impl Into<String>) -> Self {
+        self.fandom = fandom.into();
+        self
+    }
+
+    /// Creators to search for works by. May contain any characters.
+    pub fn creators(mut self, creators: impl Into<String>) -> Self {
+        self.creators = Some(creators.into());
+        self
+    }
+
+    /// AO3 language id, such as `en`, `zh`, `es`, `fr` or `ru`.
+    ///
+    /// These are the short codes AO3 uses in its own search form.
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+
+    /// Include only crossovers when `true`, or exclude them when `false`.
+    pub fn crossover(mut self, crossover: bool) -> Self {
+        self.crossover = Some(crossover);
+        self
+    }
+
+    /// Include only works with this rating.
+    pub fn rating(mut self, rating: Rating) -> Self {
+        self.rating = Some(rating);
+        self
+    }
+
+    pub fn sort_column(mut self, sort_column: SortColumn) -> Self {
+        self.sort_column = sort_column;
+        self
+    }
+
+    pub fn sort_direction(mut self, sort_direction: SortDirection) -> Self {
+        self.sort_direction = sort_direction;
+        self
+    }
+
+    /// Build the url of the given page of results, with all values percent-encoded.
+    pub fn build(&self, endpoint: &str, number: u32) -> Result<Url> {
+        let crossover = match self.crossover {
+            Some(true) => "T",
+            Some(false) => "F",
+            None => "",
+        };
+        let number = number.to_string();
+        Ok(Url::parse_with_params(
+            &format!("{}/works/search", endpoint),
+            &[
+                ("commit", "Search"),
+                ("page", number.as_str()),
+                ("utf8", "✓"),
+                ("work_search[bookmarks_count]", ""),
+                ("work_search[character_names]", ""),
+                ("work_search[comments_count]", ""),
+                ("work_search[complete]", ""),
+                (
+                    "work_search[creators]",
+                    self.creators.as_deref().unwrap_or(""),
+                ),
+                ("work_search[crossover]", crossover),
+                ("work_search[fandom_names]", self.fandom.as_str()),
+                ("work_search[freeform_names]", ""),
+                ("work_search[hits]", ""),
+                ("work_search[kudos_count]", ""),
+                (
+                    "work_search[language_id]",
+                    self.language.as_deref().unwrap_or(""),
+                ),
+                ("work_search[query]", ""),
+                (
+                    "work_search[rating_ids]",
+                    self.rating.as_ref().map_or("", Rating::to_id),
+                ),
+                ("work_search[relationship_names]", ""),
+                ("work_search[revised_at]", ""),
+                ("work_search[single_chapter]", "0"),
+                ("work_search[sort_column]", self.sort_column.to_param()),
+                (
+                    "work_search[sort_direction]",
+                    self.sort_direction.to_param(),
+                ),
+                ("work_search[title]", ""),
+                ("work_search[word_count]", ""),
+            ],
+        )?)
+    }
+}
+
+/// Get the ISO 639-1 code of a language from the name the Archive displays for it.
+///
+/// Returns `None` for languages which are not known, or which have no two letter code.
+pub fn normalize_language(name: &str) -> Option<&'static str> {
+    let code = match name {
+        "Bahasa Indonesia" => "id",
+        "Bahasa Malaysia" => "ms",
+        "Català" => "ca",
+        "Čeština" => "cs",
+        "Cymraeg" => "cy",
+        "Dansk" => "da",
+        "Deutsch" => "de",
+        "English" => "en",
+        "Español" => "es",
+        "Esperanto" => "eo",
+        "Français" => "fr",
+        "Gaeilge" => "ga",
+        "Hrvatski" => "hr",
+        "Italiano" => "it",
+        "Latviešu valoda" => "lv",
+        "Lietuvių kalba" => "lt",
+        "Lingua latina" => "la",
+        "Magyar" => "hu",
+        "Nederlands" => "nl",
+        "Norsk" => "no",
+        "Polski" => "pl",
+        "Português brasileiro" | "Português europeu" => "pt",
+        "Română" => "ro",
+        "Slovenčina" => "sk",
+        "Suomi" => "fi",
+        "Svenska" => "sv",
+        "Tiếng Việt" => "vi",
+        "Türkçe" => "tr",
+        "Ελληνικά" => "el",
+        "Български" => "bg",
+        "Русский" => "ru",
+        "Српски" => "sr",
+        "Українська" => "uk",
+        "עברית" => "he",
+        "العربية" => "ar",
+        "فارسی" => "fa",
+        "हिन्दी" => "hi",
+        "ไทย" => "th",
+        "한국어" => "ko",
+        "中文-普通话 國語" | "中文-广东话 粵語" => "zh",
+        "日本語" => "ja",
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Content rating of a work, ordered from least to most explicit.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rating {
+    NotRated,
+    General,
+    Teen,
+    Mature,
+    Explicit,
+}
+
+impl FromStr for Rating {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "not_rated" => Ok(Self::NotRated),
+            "general" => Ok(Self::General),
+            "teen" => Ok(Self::Teen),
+            "mature" => Ok(Self::Mature),
+            "explicit" => Ok(Self::Explicit),
+            _ => Err(Error::invalid_value("rating", string)),
+        }
+    }
+}
+
+/// Writes the name `FromStr` parses, such as `not_rated`. Use `to_label` for the Archive's label.
+impl fmt::Display for Rating {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::NotRated => "not_rated",
+            Self::General => "general",
+            Self::Teen => "teen",
+            Self::Mature => "mature",
+            Self::Explicit => "explicit",
+        })
+    }
+}
+
+impl Rating {
+    /// Parse the label the Archive shows for a rating, such as `Teen And Up Audiences`.
+    pub fn from_label(label: &str) -> Result<Self> {
+        match label {
+            "Not Rated" => Ok(Self::NotRated),
+            "General Audiences" => Ok(Self::General),
+            "Teen And Up Audiences" => Ok(Self::Teen),
+            "Mature" => Ok(Self::Mature),
+            "Explicit" => Ok(Self::Explicit),
+            _ => Err(Error::invalid_value("rating label", label)),
+        }
+    }
+
+    pub fn to_label(&self) -> &'static str {
+        match self {
+            Self::NotRated => "Not Rated",
+            Self::General => "General Audiences",
+            Self::Teen => "Teen And Up Audiences",
+            Self::Mature => "Mature",
+            Self::Explicit => "Explicit",
+        }
+    }
+
+    /// Id of the rating in AO3 search urls.
+    pub fn to_id(&self) -> &'static str {
+        match self {
+            Self::NotRated => "9",
+            Self::General => "10",
+            Self::Teen => "11",
+            Self::Mature => "12",
+            Self::Explicit => "13",
+        }
+    }
+}
+
+/// Column AO3 sorts search results by.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum SortColumn {
+    #[default]
+    CreatedAt,
+    RevisedAt,
+    KudosCount,
+    Hits,
+    WordCount,
+}
+
+impl FromStr for SortColumn {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "created_at" => Ok(Self::CreatedAt),
+            "revised_at" => Ok(Self::RevisedAt),
+            "kudos_count" => Ok(Self::KudosCount),
+            "hits" => Ok(Self::Hits),
+            "word_count" => Ok(Self::WordCount),
+            _ => Err(Error::invalid_value("sort column", string)),
+        }
+    }
+}
+
+impl SortColumn {
+    pub fn to_param(&self) -> &'static str {
+        match self {
+            Self::CreatedAt => "created_at",
+            Self::RevisedAt => "revised_at",
+            Self::KudosCount => "kudos_count",
+            Self::Hits => "hits",
+            Self::WordCount => "word_count",
+        }
+    }
+}
+
+/// Direction AO3 sorts search results in.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum SortDirection {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+impl FromStr for SortDirection {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "asc" => Ok(Self::Ascending),
+            "desc" => Ok(Self::Descending),
+            _ => Err(Error::invalid_value("sort direction", string)),
+        }
+    }
+}
+
+impl SortDirection {
+    pub fn to_param(&self) -> &'static str {
+        match self {
+            Self::Ascending => "asc",
+            Self::Descending => "desc",
+        }
+    }
+}
+
+// Command line options for which pages of AO3 search results to fetch, and how.
+//
+// Include in a binary's options with `#[structopt(flatten)]`. Not a doc comment, as
+// structopt would use it as the about text of the binary.
+#[derive(Debug, StructOpt)]
+pub struct SearchOpt {
+    /// Page to start fetching from
+    #[structopt(long = "start", default_value = "1")]
+    pub start: u32,
+
+    /// Number of pages to fetch at most
+    #[structopt(long = "count", default_value = "1")]
+    pub count: u32,
+
+    /// Interval between requests in seconds, to avoid rate limiting
+    #[structopt(long = "interval")]
+    pub interval: Option<u64>,
+
+    /// Fandom to fetch works from
+    #[structopt(long = "fandom", default_value = "Avatar: The Last Airbender")]
+    pub fandom: String,
+
+    /// Only fetch works by these creators
+    #[structopt(long = "creators")]
+    pub creators: Option<String>,
+
+    /// AO3 language id to restrict works to, such as `en` or `zh`
+    #[structopt(long = "language")]
+    pub language: Option<String>,
+
+    /// Column to sort works by: created_at, revised_at, kudos_count, hits or word_count
+    #[structopt(long = "sort", default_value = "created_at")]
+    pub sort: SortColumn,
+
+    /// Direction to sort works in: asc or desc
+    #[structopt(long = "order", default_value = "asc")]
+    pub order: SortDirection,
+
+    /// Only fetch crossover works
+    #[structopt(long = "crossover", conflicts_with = "no-crossover")]
+    pub crossover: bool,
+
+    /// Exclude crossover works
+    #[structopt(long = "no-crossover")]
+    pub no_crossover: bool,
+
+    /// User agent to send with requests to the Archive
+    #[structopt(long = "user-agent", default_value = DEFAULT_USER_AGENT)]
+    pub user_agent: String,
+}
+
+impl SearchOpt {
+    /// Build the search filters and ordering from the options.
+    pub fn page_url_params(&self) -> PageUrlParams {
+        let mut params = PageUrlParams::default()
+            .fandom(self.fandom.clone())
+            .sort_column(self.sort.clone())
+            .sort_direction(self.order.clone());
+        if let Some(creators) = &self.creators {
+            params = params.creators(creators.clone());
+        }
+        if let Some(language) = &self.language {
+            params = params.language(language.clone());
+        }
+        if self.crossover {
+            params = params.crossover(true);
+        } else if self.no_crossover {
+            params = params.crossover(false);
+        }
+        params
+    }
 }
 
 #[cfg(test)]
@@ -167,4 +1191,684 @@ mod tests {
             serde_json::from_str::<Vec<_>>(SEARCH_WORKS).expect("invalid test data")
         );
     }
+
+    #[test]
+    fn test_work_from_element() {
+        let fragment = Html::parse_document(SEARCH_HTML);
+        let work_element = fragment.select(&DEFAULT_SELECTORS.work).next().unwrap();
+        let works: Vec<Work> = serde_json::from_str(SEARCH_WORKS).expect("invalid test data");
+        assert_eq!(work_from_element(&work_element).unwrap(), works[0]);
+    }
+
+    #[test]
+    fn test_search_page_to_works_with_selectors() {
+        let selectors = Selectors {
+            date: Selector::parse("p.published").unwrap(),
+            ..Selectors::default()
+        };
+        let works = search_page_to_works_with(
+            r#"<ol>
+              <li class="work blurb group" id="work_1" role="article">
+                <h4 class="heading"><a href="/works/1">Title</a></h4>
+                <p class="published">03 Dec 2020</p>
+              </li>
+            </ol>"#,
+            &selectors,
+        )
+        .unwrap();
+        assert_eq!(works[0].date, NaiveDate::from_ymd_opt(2020, 12, 3).unwrap());
+    }
+
+    #[test]
+    fn test_search_page_to_works_multiple_authors() {
+        let works = search_page_to_works(
+            r#"<ol>
+              <li class="work blurb group" id="work_1" role="article">
+                <h4 class="heading">
+                  <a href="/works/1">Title</a>
+                  by
+                  <a rel="author" href="/users/one/pseuds/one">one</a>,
+                  <a rel="author" href="/users/two/pseuds/two">two</a>
+                </h4>
+                <p class="datetime">03 Dec 2020</p>
+              </li>
+            </ol>"#,
+        )
+        .unwrap();
+        assert_eq!(works[0].authors, vec!["one".to_owned(), "two".to_owned()]);
+    }
+
+    #[test]
+    fn test_search_page_to_works_updated() {
+        let works = search_page_to_works(
+            r#"<ol>
+              <li class="work blurb group" id="work_1" role="article">
+                <h4 class="heading"><a href="/works/1">Title</a></h4>
+                <p class="datetime">03 Dec 2020</p>
+                <dl class="stats">
+                  <dt class="published">Published:</dt>
+                  <dd class="published">2020-11-01</dd>
+                  <dt class="status">Updated:</dt>
+                  <dd class="status">2020-12-03</dd>
+                </dl>
+              </li>
+            </ol>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            works[0].updated,
+            Some(NaiveDate::from_ymd_opt(2020, 12, 3).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_work_page_to_work_updated() {
+        let page = |stats: &str| {
+            format!(
+                r#"<input id="kudo_commentable_id" value="1">
+                <h2 class="title">Title</h2>
+                <dl class="work meta group">
+                  <dd class="language">English</dd>
+                  <dl class="stats">{}</dl>
+                </dl>"#,
+                stats
+            )
+        };
+
+        let work = work_page_to_work(&page(
+            r#"<dt class="published">Published:</dt>
+            <dd class="published">2020-11-01</dd>
+            <dt class="status">Completed:</dt>
+            <dd class="status">03 Dec 2020</dd>"#,
+        ))
+        .unwrap();
+        assert_eq!(work.date, NaiveDate::from_ymd_opt(2020, 11, 1).unwrap());
+        assert_eq!(work.updated, NaiveDate::from_ymd_opt(2020, 12, 3));
+        assert_ne!(Some(work.date), work.updated);
+
+        // A single chapter work has no status
+        let work = work_page_to_work(&page(
+            r#"<dt class="published">Published:</dt>
+            <dd class="published">2020-11-01</dd>"#,
+        ))
+        .unwrap();
+        assert_eq!(work.date, NaiveDate::from_ymd_opt(2020, 11, 1).unwrap());
+        assert_eq!(work.updated, None);
+    }
+
+    #[test]
+    fn test_search_page_to_works_date_formats() {
+        let works = search_page_to_works(
+            r#"<ol>
+              <li class="work blurb group" id="work_1" role="article">
+                <h4 class="heading"><a href="/works/1">Title</a></h4>
+                <p class="datetime">2009-11-14</p>
+              </li>
+              <li class="work blurb group" id="work_2" role="article">
+                <h4 class="heading"><a href="/works/2">Title</a></h4>
+                <p class="datetime">14 November 2009</p>
+              </li>
+              <li class="work blurb group" id="work_3" role="article">
+                <h4 class="heading"><a href="/works/3">Title</a></h4>
+                <p class="datetime">Nov 14, 2009</p>
+              </li>
+            </ol>"#,
+        )
+        .unwrap();
+        for work in works.iter() {
+            assert_eq!(work.date, NaiveDate::from_ymd_opt(2009, 11, 14).unwrap());
+        }
+        assert_eq!(works.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_date_unknown_format() {
+        assert_eq!(
+            parse_date("14/11/2009").unwrap_err().to_string(),
+            "Failed to parse date '14/11/2009' in any format of: %d %b %Y, %Y-%m-%d, %d %B %Y, %b %d, %Y"
+        );
+    }
+
+    #[test]
+    fn test_bookmarks_page_to_works() {
+        let works = bookmarks_page_to_works(
+            r#"<ol class="bookmark index group">
+              <li id="bookmark_100" class="bookmark blurb group" role="article">
+                <div class="header module">
+                  <h4 class="heading">
+                    <a href="/works/27871338">Risen from the Ashes</a>
+                    by
+                    <a rel="author" href="/users/PixelEnchanter/pseuds/PixelEnchanter">PixelEnchanter</a>
+                  </h4>
+                  <p class="datetime">03 Dec 2020</p>
+                </div>
+                <ul class="tags commas">
+                  <li class="characters"><a class="tag" href="/tags/Zuko%20(Avatar)/works">Zuko (Avatar)</a></li>
+                </ul>
+                <dl class="stats">
+                  <dt class="language">Language:</dt>
+                  <dd class="language">English</dd>
+                  <dt class="words">Words:</dt>
+                  <dd class="words">1,784</dd>
+                </dl>
+                <div class="user module group">
+                  <p class="datetime">05 Dec 2020</p>
+                  <ul class="meta tags commas">
+                    <li><a class="tag" href="/tags/Favourites">Favourites</a></li>
+                  </ul>
+                </div>
+              </li>
+              <li id="bookmark_101" class="bookmark blurb group" role="article">
+                <div class="header module">
+                  <h4 class="heading"><a href="/series/1">A Series</a></h4>
+                  <p class="datetime">01 Dec 2020</p>
+                </div>
+              </li>
+            </ol>"#,
+        )
+        .unwrap();
+        assert_eq!(works.len(), 1);
+        let work = &works[0];
+        assert_eq!(work.id, "27871338");
+        assert_eq!(work.title, "Risen from the Ashes");
+        assert_eq!(work.authors, vec!["PixelEnchanter".to_owned()]);
+        assert_eq!(work.characters, vec!["Zuko (Avatar)".to_owned()]);
+        assert!(work.freeforms.is_empty());
+        assert_eq!(work.date, NaiveDate::from_ymd_opt(2020, 12, 3).unwrap());
+        assert_eq!(work.words, 1784);
+    }
+
+    #[test]
+    fn test_bookmarks_page_to_works_deleted() {
+        let works = bookmarks_page_to_works(
+            r#"<ol class="bookmark index group">
+              <li id="bookmark_102" class="bookmark blurb group" role="article">
+                <p class="message">This has been deleted, sorry!</p>
+                <div class="user module group">
+                  <p class="datetime">05 Dec 2020</p>
+                </div>
+              </li>
+              <li id="bookmark_103" class="bookmark blurb group" role="article">
+                <div class="header module">
+                  <h4 class="heading"><a href="/works/1">Title</a></h4>
+                  <p class="datetime">03 Dec 2020</p>
+                </div>
+              </li>
+            </ol>"#,
+        )
+        .unwrap();
+        assert_eq!(works.len(), 1);
+        assert_eq!(works[0].id, "1");
+    }
+
+    #[test]
+    fn test_work_page_to_work() {
+        assert_eq!(
+            work_page_to_work(include_str!("work.html")).unwrap(),
+            Work {
+                id: "27871338".to_owned(),
+                title: "Risen from the Ashes".to_owned(),
+                authors: vec!["PixelEnchanter".to_owned()],
+                relationships: vec!["Zuko & Uncle Iroh".to_owned()],
+                characters: vec![
+                    "Zuko (Avatar)".to_owned(),
+                    "Sozin (Avatar)".to_owned(),
+                    "Azulon (Avatar)".to_owned(),
+                    "Iroh (Avatar)".to_owned(),
+                ],
+                freeforms: vec![
+                    "Avatar Zuko (Avatar)".to_owned(),
+                    "Alternate Universe - Canon Divergence".to_owned(),
+                    "Other Additional Tags to Be Added".to_owned(),
+                    "Reincarnation".to_owned(),
+                    "Time Travel".to_owned(),
+                ],
+                tags_truncated: false,
+                date: NaiveDate::from_ymd_opt(2020, 12, 3).unwrap(),
+                updated: NaiveDate::from_ymd_opt(2020, 12, 10),
+                language: "English".to_owned(),
+                words: 3921,
+                chapters: 2,
+                kudos: 41,
+                hits: 1203,
+                comments: 5,
+                bookmarks: 6,
+                summary: Some(
+                    "Zuko dies at the hands of Azula, and wakes in the body of a boy in the court of Fire Lord Sozin.\n\nThis time, he will not let the war begin."
+                        .to_owned()
+                ),
+                tag_hrefs: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_search_page_to_works_tags_truncated() {
+        let works = search_page_to_works(
+            r#"<ol>
+              <li class="work blurb group" id="work_1" role="article">
+                <h4 class="heading"><a href="/works/1">Title</a></h4>
+                <ul class="tags commas">
+                  <li class="characters"><a class="tag" href="/tags/Zuko%20(Avatar)/works">Zuko (Avatar)</a></li>
+                  <li class="more"><a href="/works/1">Show more tags</a></li>
+                </ul>
+                <p class="datetime">03 Dec 2020</p>
+              </li>
+              <li class="work blurb group" id="work_2" role="article">
+                <h4 class="heading"><a href="/works/2">Title</a></h4>
+                <ul class="tags commas">
+                  <li class="characters"><a class="tag" href="/tags/Zuko%20(Avatar)/works">Zuko (Avatar)</a></li>
+                </ul>
+                <p class="datetime">03 Dec 2020</p>
+              </li>
+            </ol>"#,
+        )
+        .unwrap();
+        assert!(works[0].tags_truncated);
+        assert_eq!(works[0].characters, vec!["Zuko (Avatar)".to_owned()]);
+        assert!(!works[1].tags_truncated);
+    }
+
+    #[test]
+    fn test_bookmarks_url() {
+        assert_eq!(
+            bookmarks_url(ENDPOINT_AO3, "Some User", 2)
+                .unwrap()
+                .as_str(),
+            "https://archiveofourown.org/users/Some%20User/bookmarks?page=2"
+        );
+    }
+
+    #[test]
+    fn test_search_page_tag_hrefs() {
+        let hrefs = search_page_tag_hrefs(
+            r#"<ol>
+              <li class="work blurb group" id="work_1" role="article">
+                <ul class="tags commas">
+                  <li class="relationships"><a class="tag" href="/tags/Katara*s*Zuko%20(Avatar)/works">Katara/Zuko (Avatar)</a></li>
+                  <li class="characters"><a class="tag" href="/tags/Zuko%20(Avatar)/works">Zuko (Avatar)</a></li>
+                  <li class="characters"><a class="tag" href="/tags/Katara%20(Avatar)/works">Katara (Avatar)</a></li>
+                </ul>
+              </li>
+            </ol>"#,
+        );
+        assert_eq!(
+            hrefs["1"],
+            TagHrefs {
+                relationships: vec!["/tags/Katara*s*Zuko%20(Avatar)/works".to_owned()],
+                characters: vec![
+                    "/tags/Zuko%20(Avatar)/works".to_owned(),
+                    "/tags/Katara%20(Avatar)/works".to_owned()
+                ],
+                freeforms: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_work_json_without_tag_hrefs() {
+        let works: Vec<Work> = serde_json::from_str(SEARCH_WORKS).expect("invalid test data");
+        assert!(works[0].tag_hrefs.is_none());
+        assert!(serde_json::to_value(&works[0])
+            .unwrap()
+            .get("tag_hrefs")
+            .is_none());
+    }
+
+    #[test]
+    fn test_work_json_single_author() {
+        let works: Vec<Work> = serde_json::from_str(SEARCH_WORKS).expect("invalid test data");
+        let mut work = serde_json::to_value(&works[0]).unwrap();
+        let fields = work.as_object_mut().unwrap();
+        fields.remove("authors");
+        fields.insert("author".to_owned(), "PixelEnchanter".into());
+        let parsed: Work = serde_json::from_value(work.clone()).unwrap();
+        assert_eq!(parsed.authors, vec!["PixelEnchanter".to_owned()]);
+
+        // Anonymous works had no author
+        work["author"] = serde_json::Value::Null;
+        let parsed: Work = serde_json::from_value(work).unwrap();
+        assert!(parsed.authors.is_empty());
+    }
+
+    #[test]
+    fn test_search_page_to_works_lenient() {
+        let (works, errors) = search_page_to_works_lenient(
+            r#"<ol>
+              <li class="work blurb group" id="work_1" role="article">
+                <h4 class="heading"><a href="/works/1">Title</a></h4>
+                <p class="datetime">03 Dec 2020</p>
+              </li>
+              <li class="work blurb group" id="work_2" role="article">
+                <h4 class="heading"><a href="/works/2">Deleted</a></h4>
+              </li>
+            </ol>"#,
+        );
+        assert_eq!(
+            works
+                .iter()
+                .map(|work| work.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["1"]
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].id, Some("2".to_owned()));
+    }
+
+    #[test]
+    fn test_search_page_to_works_lenient_with_selectors() {
+        let selectors = Selectors {
+            date: Selector::parse("p.published").unwrap(),
+            ..Selectors::default()
+        };
+        let (works, errors) = search_page_to_works_lenient_with(
+            r#"<ol>
+              <li class="work blurb group" id="work_1" role="article">
+                <h4 class="heading"><a href="/works/1">Title</a></h4>
+                <p class="published">03 Dec 2020</p>
+              </li>
+              <li class="work blurb group" id="work_2" role="article">
+                <h4 class="heading"><a href="/works/2">Title</a></h4>
+                <p class="datetime">03 Dec 2020</p>
+              </li>
+            </ol>"#,
+            &selectors,
+        );
+        assert_eq!(works.len(), 1);
+        assert_eq!(works[0].date, NaiveDate::from_ymd_opt(2020, 12, 3).unwrap());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].id, Some("2".to_owned()));
+    }
+
+    #[test]
+    fn test_search_page_to_works_decodes_entities() {
+        let works = search_page_to_works(
+            r#"<ol>
+              <li class="work blurb group" id="work_1" role="article">
+                <h4 class="heading"><a href="/works/1">Sokka&#39;s Boomerang &amp; Other Stories</a></h4>
+                <p class="datetime">03 Dec 2020</p>
+                <ul class="tags commas">
+                  <li class='relationships'><a class="tag" href="/tags/Steven*s*Connie/works">Steven &amp; Connie</a></li>
+                </ul>
+              </li>
+            </ol>"#,
+        )
+        .unwrap();
+        assert_eq!(works[0].title, "Sokka's Boomerang & Other Stories");
+        assert!(works[0].authors.is_empty());
+        assert_eq!(works[0].relationships, vec!["Steven & Connie".to_owned()]);
+    }
+
+    #[test]
+    fn test_search_page_info() {
+        let info = search_page_info(SEARCH_HTML);
+        assert_eq!(
+            info,
+            SearchPageInfo {
+                total_pages: Some(1085),
+                no_results: false,
+            }
+        );
+        assert!(!info.is_last_page(2));
+        assert!(info.is_last_page(1085));
+    }
+
+    #[test]
+    fn test_search_page_info_no_results() {
+        let info = search_page_info(
+            r#"<div id="main"><h3 class="heading">No results found. You may want to edit your search to make it less specific.</h3></div>"#,
+        );
+        assert_eq!(
+            info,
+            SearchPageInfo {
+                total_pages: None,
+                no_results: true,
+            }
+        );
+        assert!(info.is_last_page(1));
+    }
+
+    #[test]
+    fn test_search_page_info_no_results_in_summary() {
+        let info = search_page_info(
+            r#"<div id="main">
+              <h3 class="heading">1 Found</h3>
+              <ol>
+                <li class="work blurb group" id="work_1" role="article">
+                  <blockquote class="userstuff summary"><p>No results found, said the search engine.</p></blockquote>
+                </li>
+              </ol>
+            </div>"#,
+        );
+        assert!(!info.no_results);
+        assert!(!info.is_last_page(1));
+    }
+
+    #[test]
+    fn test_authenticity_token() {
+        assert_eq!(
+            authenticity_token(
+                r#"<form class="new_user" id="new_user" action="/users/login" method="post">
+                  <input type="hidden" name="authenticity_token" value="abc123==" />
+                  <input type="text" name="user[login]" id="user_login" />
+                </form>"#
+            )
+            .unwrap(),
+            "abc123=="
+        );
+        assert!(authenticity_token("<html></html>").is_err());
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        for attempt in 0..4 {
+            let backoff = RETRY_BASE_DELAY * 2u32.pow(attempt);
+            let delay = retry_delay(attempt);
+            assert!(delay >= backoff && delay < backoff * 2);
+        }
+    }
+
+    #[test]
+    fn test_kudos_hits_ratio() {
+        let mut works: Vec<Work> = serde_json::from_str(SEARCH_WORKS).expect("invalid test data");
+        let work = &mut works[0];
+        work.kudos = 18;
+        work.hits = 72;
+        assert_eq!(work.kudos_hits_ratio(), Some(0.25));
+        work.hits = 0;
+        assert_eq!(work.kudos_hits_ratio(), None);
+    }
+
+    #[test]
+    fn test_words_per_chapter() {
+        let mut works: Vec<Work> = serde_json::from_str(SEARCH_WORKS).expect("invalid test data");
+        let work = &mut works[0];
+        work.words = 9000;
+        work.chapters = 4;
+        assert_eq!(work.words_per_chapter(), Some(2250.));
+        work.chapters = 0;
+        assert_eq!(work.words_per_chapter(), None);
+    }
+
+    #[test]
+    fn test_search_page_to_works_chapters() {
+        let works = search_page_to_works(
+            r#"<ol>
+              <li class="work blurb group" id="work_1" role="article">
+                <h4 class="heading"><a href="/works/1">Title</a></h4>
+                <p class="datetime">03 Dec 2020</p>
+                <dl class="stats">
+                  <dt class="chapters">Chapters:</dt>
+                  <dd class="chapters"><a href="/works/1/chapters/2">1,024</a>/?</dd>
+                </dl>
+              </li>
+            </ol>"#,
+        )
+        .unwrap();
+        assert_eq!(works[0].chapters, 1024);
+    }
+
+    #[test]
+    fn test_normalize_language() {
+        assert_eq!(normalize_language("English"), Some("en"));
+        assert_eq!(normalize_language("中文-普通话 國語"), Some("zh"));
+        assert_eq!(normalize_language("Português europeu"), Some("pt"));
+        assert_eq!(normalize_language("Klingon"), None);
+        assert_eq!(normalize_language(""), None);
+    }
+
+    #[test]
+    fn test_rating_from_str() {
+        assert_eq!(Rating::from_str("teen").unwrap(), Rating::Teen);
+        assert_eq!(Rating::from_str("not_rated").unwrap(), Rating::NotRated);
+        assert!(Rating::from_str("Teen").is_err());
+    }
+
+    #[test]
+    fn test_rating_label() {
+        for rating in [
+            Rating::NotRated,
+            Rating::General,
+            Rating::Teen,
+            Rating::Mature,
+            Rating::Explicit,
+        ]
+        .iter()
+        {
+            assert_eq!(&Rating::from_label(rating.to_label()).unwrap(), rating);
+            assert_eq!(&Rating::from_str(&rating.to_string()).unwrap(), rating);
+        }
+        assert!(Rating::from_label("Adult").is_err());
+    }
+
+    #[test]
+    fn test_rating_order() {
+        assert!(Rating::Explicit > Rating::General);
+        assert!(Rating::Teen <= Rating::Teen);
+        assert!(Rating::NotRated < Rating::General);
+        assert_eq!(
+            serde_json::to_string(&Rating::NotRated).unwrap(),
+            "\"not_rated\""
+        );
+    }
+
+    #[test]
+    fn test_work_url() {
+        let works: Vec<Work> = serde_json::from_str(SEARCH_WORKS).expect("invalid test data");
+        assert_eq!(
+            works[0].url(ENDPOINT_AO3),
+            "https://archiveofourown.org/works/27871338"
+        );
+    }
+
+    #[test]
+    fn test_work_csv() {
+        let works: Vec<Work> = serde_json::from_str(SEARCH_WORKS).expect("invalid test data");
+        let mut writer = csv::Writer::from_writer(vec![]);
+        Work::write_csv_header(&mut writer).unwrap();
+        for work in works.iter().take(2) {
+            work.write_csv_record(&mut writer).unwrap();
+        }
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        let mut lines = output.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "id,title,authors,relationships,characters,freeforms,date,updated,language,words,chapters,kudos,hits,comments,bookmarks"
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            "27871338,Risen from the Ashes,PixelEnchanter,,Zuko (Avatar)|Sozin (Avatar)|Azulon (Avatar),Avatar Zuko (Avatar)|Alternate Universe - Canon Divergence|Other Additional Tags to Be Added,2020-12-03,,English,1784,1,18,83,2,2"
+        );
+        // Commas in the title are quoted
+        assert!(lines
+            .next()
+            .unwrap()
+            .contains(",\"You Can Break my Heart a Million Times, But Please Don’t Lie\","));
+    }
+
+    #[test]
+    fn test_work_read_csv() {
+        let works: Vec<Work> = serde_json::from_str(SEARCH_WORKS).expect("invalid test data");
+        let mut writer = csv::Writer::from_writer(vec![]);
+        Work::write_csv_header(&mut writer).unwrap();
+        for work in works.iter() {
+            work.write_csv_record(&mut writer).unwrap();
+        }
+        let output = writer.into_inner().unwrap();
+
+        let read: Vec<Work> = Work::read_csv(output.as_slice())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read, works);
+    }
+
+    #[test]
+    fn test_page_url_encodes_fandom_and_creators() {
+        let url = page_url(
+            ENDPOINT_AO3,
+            2,
+            "Avatar: The Last Airbender & Legend/Korra",
+            Some("some author?"),
+            None,
+            None,
+            &SortColumn::default(),
+            &SortDirection::default(),
+        )
+        .unwrap();
+
+        assert!(url.as_str().starts_with(
+            "https://archiveofourown.org/works/search?commit=Search&page=2&utf8=%E2%9C%93&"
+        ));
+        assert!(url.as_str().contains(
+            "&work_search%5Bfandom_names%5D=Avatar%3A+The+Last+Airbender+%26+Legend%2FKorra&"
+        ));
+        assert!(url
+            .as_str()
+            .contains("&work_search%5Bcreators%5D=some+author%3F&"));
+
+        let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        assert!(pairs.contains(&(
+            "work_search[fandom_names]".to_owned(),
+            "Avatar: The Last Airbender & Legend/Korra".to_owned()
+        )));
+        assert!(pairs.contains(&(
+            "work_search[creators]".to_owned(),
+            "some author?".to_owned()
+        )));
+    }
+
+    #[test]
+    fn test_page_url_params() {
+        let url = PageUrlParams::default()
+            .fandom("Avatar: The Last Airbender")
+            .language("en")
+            .crossover(false)
+            .rating(Rating::Explicit)
+            .sort_column(SortColumn::KudosCount)
+            .sort_direction(SortDirection::Descending)
+            .build(ENDPOINT_AO3, 1)
+            .unwrap();
+
+        let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        for (key, value) in [
+            ("page", "1"),
+            ("work_search[fandom_names]", "Avatar: The Last Airbender"),
+            ("work_search[creators]", ""),
+            ("work_search[language_id]", "en"),
+            ("work_search[crossover]", "F"),
+            ("work_search[rating_ids]", "13"),
+            ("work_search[sort_column]", "kudos_count"),
+            ("work_search[sort_direction]", "desc"),
+        ]
+        .iter()
+        {
+            assert!(
+                pairs.contains(&((*key).to_owned(), (*value).to_owned())),
+                "missing {}={}",
+                key,
+                value
+            );
+        }
+    }
 }
@@ -1,41 +1,1070 @@
-use anyhow::{anyhow, Context, Error, Result};
-use elasticsearch::{Elasticsearch, SearchParts};
+use crate::{
+    error::{Context, Error, Result},
+    scrape::{normalize_language, Work},
+    ship::{Ship, ShipKind},
+};
+use chrono::{DateTime, NaiveDate};
+use elasticsearch::{
+    http::response::Response,
+    indices::{IndicesCreateParts, IndicesExistsParts, IndicesPutMappingParts},
+    BulkOperation, BulkOperations, BulkParts, Elasticsearch, MgetParts, SearchParts,
+};
+use once_cell::sync::Lazy;
 use serde::Serialize;
 use serde_json::{json, Value};
-use std::str::FromStr;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
 
 const WORKS_INDEX: &str = "works";
 const AGGREGATION_KEY: &str = "aggregation_key";
 
+/// A work as indexed, with values derived from it precomputed for sorting and aggregating.
+#[derive(Debug, Serialize)]
+struct IndexedWork {
+    #[serde(flatten)]
+    work: Work,
+    kudos_hits_ratio: Option<f64>,
+    words_per_chapter: Option<f64>,
+    /// ISO 639-1 code of the language, so works can be grouped regardless of display name.
+    language_code: Option<&'static str>,
+}
+
+impl From<Work> for IndexedWork {
+    fn from(work: Work) -> Self {
+        Self {
+            kudos_hits_ratio: work.kudos_hits_ratio(),
+            words_per_chapter: work.words_per_chapter(),
+            language_code: normalize_language(&work.language),
+            work,
+        }
+    }
+}
+
+/// A text field with a `.keyword` subfield to aggregate on, as elasticsearch maps
+/// strings dynamically.
+fn text_with_keyword() -> Value {
+    json!({
+      "type": "text",
+      "fields": {
+        "keyword": {
+          "type": "keyword",
+          "ignore_above": 256
+        }
+      }
+    })
+}
+
+/// Mapping of the works index, matching the fields of `IndexedWork`.
+///
+/// Fields are mapped as they would be dynamically where possible, so the mapping can
+/// also be put on an index created by indexing works without it.
+static MAPPING_WORKS: Lazy<Value> = Lazy::new(|| {
+    json!({
+      "properties": {
+        "id": text_with_keyword(),
+        "title": text_with_keyword(),
+        "authors": text_with_keyword(),
+        TagKind::Relationship.to_field(): text_with_keyword(),
+        TagKind::Character.to_field(): text_with_keyword(),
+        TagKind::Freeform.to_field(): text_with_keyword(),
+        "tags_truncated": {
+          "type": "boolean"
+        },
+        "date": {
+          "type": "date"
+        },
+        "updated": {
+          "type": "date"
+        },
+        "language": text_with_keyword(),
+        "language_code": text_with_keyword(),
+        "words": {
+          "type": "long"
+        },
+        "chapters": {
+          "type": "long"
+        },
+        "kudos": {
+          "type": "long"
+        },
+        "hits": {
+          "type": "long"
+        },
+        "comments": {
+          "type": "long"
+        },
+        "bookmarks": {
+          "type": "long"
+        },
+        // Ratios are stored as integers of thousandths, which is more compact than a
+        // double and precise enough to sort and aggregate by
+        "kudos_hits_ratio": {
+          "type": "scaled_float",
+          "scaling_factor": 1000
+        },
+        "words_per_chapter": {
+          "type": "float"
+        },
+      }
+    })
+});
+
+/// Create the works index with its mapping, or put the mapping on an existing index.
+///
+/// An existing index mapped dynamically may have conflicting types, such as a float
+/// `kudos_hits_ratio`. Queries still work on these, so its mapping is kept with a warning.
+pub async fn put_works_mapping(client: &Elasticsearch) -> Result<()> {
+    let indices = client.indices();
+    let exists = indices
+        .exists(IndicesExistsParts::Index(&[WORKS_INDEX]))
+        .send()
+        .await?;
+    if exists.status_code().as_u16() == 404 {
+        let response = indices
+            .create(IndicesCreateParts::Index(WORKS_INDEX))
+            .body(json!({ "mappings": &*MAPPING_WORKS }))
+            .send()
+            .await?;
+        response_json(response).await?;
+        return Ok(());
+    }
+
+    let response = indices
+        .put_mapping(IndicesPutMappingParts::Index(&[WORKS_INDEX]))
+        .body(&*MAPPING_WORKS)
+        .send()
+        .await?;
+    match response_json(response).await {
+        Err(Error::ElasticsearchResponse { status: 400, body }) => {
+            log::warn!(
+                "Keeping the existing mapping of the works index, which conflicts: {}",
+                body
+            );
+            Ok(())
+        }
+        result => result.map(|_| ()),
+    }
+}
+
+/// Index works in a single bulk request, replacing any with the same id.
+pub async fn index_works(client: &Elasticsearch, works: Vec<Work>) -> Result<()> {
+    let mut ops = BulkOperations::new();
+    for work in works {
+        let id = work.id.clone();
+        ops.push(BulkOperation::index(IndexedWork::from(work)).id(id))?;
+    }
+    let response = client
+        .bulk(BulkParts::Index(WORKS_INDEX))
+        .body(vec![ops])
+        .send()
+        .await?;
+    bulk_errors(&response_json(response).await?)
+}
+
+/// Check each item of a bulk response, which may fail even if the request succeeded.
+fn bulk_errors(response_body: &Value) -> Result<()> {
+    if !response_body["errors"].as_bool().unwrap_or_default() {
+        return Ok(());
+    }
+    let failures: Vec<&Value> = response_body["items"]
+        .as_array()
+        .context("Bulk response items")?
+        .iter()
+        .filter_map(|item| item["index"].get("error").map(|_| &item["index"]))
+        .collect();
+    let first = failures.first().context("Bulk response failed item")?;
+    Err(Error::BulkIndex {
+        failed: failures.len(),
+        id: first["_id"].as_str().unwrap_or_default().to_owned(),
+        reason: first["error"]["reason"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned(),
+    })
+}
+
+/// Find which of the given work ids are already indexed.
+pub async fn existing_work_ids(client: &Elasticsearch, ids: &[&str]) -> Result<HashSet<String>> {
+    let docs: Vec<Value> = ids
+        .iter()
+        .map(|id| json!({ "_id": id, "_source": false }))
+        .collect();
+    let response = client
+        .mget(MgetParts::Index(WORKS_INDEX))
+        .body(json!({ "docs": docs }))
+        .send()
+        .await?;
+    // Nothing has been indexed yet if the works index does not exist
+    if response.status_code().as_u16() == 404 {
+        return Ok(HashSet::default());
+    }
+
+    let response_body = response_json(response).await?;
+    parse_existing_ids(&response_body)
+}
+
+fn parse_existing_ids(response_body: &Value) -> Result<HashSet<String>> {
+    let mut ids = HashSet::default();
+    for doc in response_body
+        .get("docs")
+        .context("Response docs key")?
+        .as_array()
+        .context("Response docs array")?
+        .iter()
+    {
+        if doc.get("found").and_then(Value::as_bool).unwrap_or(false) {
+            ids.insert(
+                doc.get("_id")
+                    .context("doc id")?
+                    .as_str()
+                    .context("doc id string")?
+                    .to_owned(),
+            );
+        }
+    }
+    Ok(ids)
+}
+
+/// Load the `limit` works with the highest value of a numeric field, such as `kudos`.
+pub async fn top_works(
+    client: &Elasticsearch,
+    filter: Option<WorksQuery>,
+    sort_field: &str,
+    limit: usize,
+) -> Result<Vec<Work>> {
+    let query = filter.map(|filter| filter.to_query()).unwrap_or(json!({
+      "match_all": {}
+    }));
+
+    let response = client
+        .search(SearchParts::Index(&[WORKS_INDEX]))
+        .body(json!({
+          "size": limit,
+          "sort": [
+            { sort_field: "desc" }
+          ],
+          "query": query
+        }))
+        .allow_no_indices(true)
+        .send()
+        .await?;
+
+    let response_body = response_json(response).await?;
+    parse_hits(&response_body)
+}
+
+/// Get the works in the hits of a search response.
+fn parse_hits(response_body: &Value) -> Result<Vec<Work>> {
+    response_body
+        .get("hits")
+        .and_then(|hits| hits.get("hits"))
+        .context("Response hits key")?
+        .as_array()
+        .context("Response hits array")?
+        .iter()
+        .map(|hit| {
+            serde_json::from_value(hit.get("_source").context("hit source")?.clone())
+                .context("hit source work")
+        })
+        .collect()
+}
+
+/// Build a query matching works dated between `after` and `before`, inclusive.
+///
+/// Either bound may be omitted. Prefer `WorksQuery::date_range` to use this as a filter.
+pub fn date_range(after: Option<NaiveDate>, before: Option<NaiveDate>) -> Value {
+    let mut range = serde_json::Map::new();
+    if let Some(after) = after {
+        range.insert("gte".to_owned(), json!(after));
+    }
+    if let Some(before) = before {
+        range.insert("lte".to_owned(), json!(before));
+    }
+    json!({
+      "range": {
+        "date": range
+      }
+    })
+}
+
+/// Filters on works, which compile to an Elasticsearch bool query.
+///
+/// All filters must match. The default query matches all works.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct WorksQuery {
+    filters: Vec<Value>,
+}
+
+impl WorksQuery {
+    /// Match works with at least this many kudos.
+    pub fn min_kudos(mut self, kudos: u32) -> Self {
+        self.filters.push(json!({
+          "range": {
+            "kudos": {
+              "gte": kudos
+            }
+          }
+        }));
+        self
+    }
+
+    /// Match works in this language, as scraped (such as `English`).
+    pub fn language(mut self, language: &str) -> Self {
+        self.filters.push(json!({
+          "term": {
+            "language.keyword": language
+          }
+        }));
+        self
+    }
+
+    /// Match works dated between `after` and `before`, inclusive.
+    ///
+    /// Either bound may be omitted, and if both are this is a no-op.
+    pub fn date_range(mut self, after: Option<NaiveDate>, before: Option<NaiveDate>) -> Self {
+        if after.is_some() || before.is_some() {
+            self.filters.push(date_range(after, before));
+        }
+        self
+    }
+
+    /// Match works tagged with this relationship.
+    pub fn relationship(mut self, tag: &str) -> Self {
+        self.filters.push(json!({
+          "term": {
+            TagKind::Relationship.to_keyword_field(): tag
+          }
+        }));
+        self
+    }
+
+    pub fn to_query(&self) -> Value {
+        json!({
+          "bool": {
+            "filter": self.filters
+          }
+        })
+    }
+}
+
+/// Number of works with a tag.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize)]
+pub struct TagFrequency {
+    pub tag: String,
+    pub count: u64,
+}
+
+/// Tags which are significant for the works of a ship.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct SignificantTags {
+    /// The tag grouped by, which is a ship unless grouping by another tag kind.
+    pub ship: String,
+    pub tags: Vec<SignificantTag>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct SignificantTag {
+    pub tag: String,
+    pub score: f64,
+}
+
+/// Percentiles of a metric for the works with a tag.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct TagPercentiles {
+    pub tag: String,
+    /// Map of percentile (formatted by Elasticsearch, such as `"50.0"`) to value.
+    pub percentiles: HashMap<String, f64>,
+}
+
+/// Number of works in which two characters, or other tags, appear together.
+///
+/// The two are in sorted order.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize)]
+pub struct CoOccurrence {
+    pub first: String,
+    pub second: String,
+    pub count: u64,
+}
+
+/// Number of works with a tag over time.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TagHistogram {
+    pub tag: String,
+    pub counts: Vec<(NaiveDate, u64)>,
+}
+
 /// Load the frequencies of ship tags from all works.
 ///
-/// Returns a list of `(ship name, count)` pairs.
-pub async fn ship_frequencies(
+/// If `ship_kind` is given, only tags which look like that kind of ship are counted.
+pub async fn ship_frequencies(
+    client: &Elasticsearch,
+    min_works: usize,
+    limit: usize,
+    field: TagKind,
+    filter: Option<WorksQuery>,
+    ship_kind: Option<&ShipKind>,
+) -> Result<Vec<TagFrequency>> {
+    ship_frequencies_in(
+        client,
+        &[WORKS_INDEX],
+        min_works,
+        limit,
+        field,
+        filter,
+        ship_kind,
+    )
+    .await
+}
+
+/// Load the frequencies of ship tags from all works across several indices,
+/// such as one per fandom.
+pub async fn ship_frequencies_in(
+    client: &Elasticsearch,
+    indices: &[&str],
+    min_works: usize,
+    limit: usize,
+    field: TagKind,
+    filter: Option<WorksQuery>,
+    ship_kind: Option<&ShipKind>,
+) -> Result<Vec<TagFrequency>> {
+    let mut aggregation = terms_aggregation(&field.to_keyword_field(), min_works, limit);
+    if let Some(ship_kind) = ship_kind {
+        restrict_to_ship_kind(&mut aggregation, ship_kind);
+    }
+    term_frequencies(
+        client,
+        indices,
+        aggregation,
+        filter,
+        &CoOccurrenceWeight::Works,
+    )
+    .await
+}
+
+/// Load the frequencies of authors from all works.
+///
+/// Anonymous works have no authors, and are not counted. Co-authored works
+/// are counted once for each author.
+pub async fn author_frequencies(
+    client: &Elasticsearch,
+    min_works: usize,
+    limit: usize,
+) -> Result<Vec<TagFrequency>> {
+    term_frequencies(
+        client,
+        &[WORKS_INDEX],
+        terms_aggregation("authors.keyword", min_works, limit),
+        None,
+        &CoOccurrenceWeight::Works,
+    )
+    .await
+}
+
+/// Load the frequencies of languages from all works.
+pub async fn language_frequencies(
+    client: &Elasticsearch,
+    limit: usize,
+) -> Result<Vec<TagFrequency>> {
+    term_frequencies(
+        client,
+        &[WORKS_INDEX],
+        terms_aggregation("language.keyword", 1, limit),
+        None,
+        &CoOccurrenceWeight::Works,
+    )
+    .await
+}
+
+/// Load the frequencies of every tag of kind `field` used by at least `min_works` works,
+/// most frequent first.
+///
+/// Unlike `ship_frequencies`, tags are not limited to the top buckets; they are
+/// paged through `page_size` at a time with a composite aggregation.
+pub async fn all_tag_frequencies(
+    client: &Elasticsearch,
+    field: TagKind,
+    min_works: usize,
+    page_size: usize,
+) -> Result<Vec<TagFrequency>> {
+    let mut frequencies = Vec::new();
+    let mut after = None;
+    loop {
+        let response = client
+            .search(SearchParts::Index(&[WORKS_INDEX]))
+            .body(json!({
+              "aggs": {
+                AGGREGATION_KEY: composite_aggregation(&field.to_keyword_field(), page_size, after)
+              },
+              "size": 0
+            }))
+            .send()
+            .await?;
+        let response_body = response_json(response).await?;
+        let (page, after_key) = parse_composite_page(&response_body)?;
+        frequencies.extend(page);
+        match after_key {
+            Some(after_key) => after = Some(after_key),
+            None => break,
+        }
+    }
+    // Composite aggregations can't filter by count, so drop rare tags afterwards
+    frequencies.retain(|frequency| frequency.count >= min_works as u64);
+    frequencies.sort_by_key(|frequency| std::cmp::Reverse(frequency.count));
+    Ok(frequencies)
+}
+
+/// Composite aggregation over every value of a keyword field, for the page after the key `after`.
+fn composite_aggregation(field: &str, page_size: usize, after: Option<Value>) -> Value {
+    let mut aggregation = json!({
+      "composite": {
+        "size": page_size,
+        "sources": [
+          { "tag": { "terms": { "field": field } } }
+        ]
+      }
+    });
+    if let Some(after) = after {
+        aggregation["composite"]["after"] = after;
+    }
+    aggregation
+}
+
+/// Parse a page of a composite aggregation, with the key to request the next page after.
+///
+/// The key is `None` once there are no more pages.
+fn parse_composite_page(response_body: &Value) -> Result<(Vec<TagFrequency>, Option<Value>)> {
+    let buckets = buckets(response_body)?;
+    let frequencies = buckets
+        .iter()
+        .map(|bucket| {
+            Ok(TagFrequency {
+                tag: bucket
+                    .get("key")
+                    .and_then(|key| key.get("tag"))
+                    .and_then(Value::as_str)
+                    .context("composite bucket tag")?
+                    .to_owned(),
+                count: bucket_count(bucket)?,
+            })
+        })
+        .collect::<Result<_>>()?;
+    let after_key = if buckets.is_empty() {
+        None
+    } else {
+        response_body["aggregations"][AGGREGATION_KEY]
+            .get("after_key")
+            .cloned()
+    };
+    Ok((frequencies, after_key))
+}
+
+/// Terms aggregation for the most frequent values of a keyword field.
+///
+/// Works missing the field are not counted.
+fn terms_aggregation(field: &str, min_works: usize, limit: usize) -> Value {
+    json!({
+      "terms": {
+        "field": field,
+        "min_doc_count": min_works,
+        "size": limit,
+        "order": {
+          "_count": "desc"
+        },
+      }
+    })
+}
+
+/// Only bucket ship tags with the separator of `ship_kind`, as parsed by `Ship::from_str`.
+///
+/// Tags containing both separators are romantic.
+fn restrict_to_ship_kind(aggregation: &mut Value, ship_kind: &ShipKind) {
+    let terms = &mut aggregation["terms"];
+    match ship_kind {
+        ShipKind::Romantic => {
+            terms["include"] = json!(".*/.*");
+        }
+        ShipKind::Platonic => {
+            // `&` is an operator in Lucene regular expressions, so must be escaped
+            terms["include"] = json!(".*\\&.*");
+            terms["exclude"] = json!(".*/.*");
+        }
+    }
+}
+
+/// Sum `weight` within each bucket of `aggregation`, if it is not just the number of works.
+fn add_weight_sum(aggregation: &mut Value, weight: &CoOccurrenceWeight) {
+    if let Some(field) = weight.to_field() {
+        aggregation["aggs"] = json!({
+          AGGREGATION_KEY: {
+            "sum": {
+              "field": field
+            }
+          }
+        });
+    }
+}
+
+/// Load the buckets of a terms `aggregation` over all works in `indices`,
+/// counting each by `weight`.
+async fn term_frequencies(
+    client: &Elasticsearch,
+    indices: &[&str],
+    mut aggregation: Value,
+    filter: Option<WorksQuery>,
+    weight: &CoOccurrenceWeight,
+) -> Result<Vec<TagFrequency>> {
+    add_weight_sum(&mut aggregation, weight);
+    let query = filter.map(|filter| filter.to_query()).unwrap_or(json!({
+      "match_all": {}
+    }));
+
+    let response = client
+        .search(SearchParts::Index(indices))
+        .body(json!({
+          "aggs": {
+            AGGREGATION_KEY: aggregation
+          },
+          "size": 0,
+          "query": query
+        }))
+        .allow_no_indices(true)
+        .send()
+        .await?;
+
+    let response_body = response_json(response).await?;
+    buckets(&response_body)?
+        .iter()
+        .map(|bucket| {
+            Ok(TagFrequency {
+                tag: bucket_key(bucket)?,
+                count: bucket_weight(bucket, weight)?,
+            })
+        })
+        .collect()
+}
+
+/// Load the tags of kind `field` that are significant for each of the most
+/// common tags of kind `group_by`, such as the freeforms of each ship.
+pub async fn significant_tags(
+    client: &Elasticsearch,
+    min_works: usize,
+    limit: usize,
+    group_by: TagKind,
+    field: TagKind,
+) -> Result<Vec<SignificantTags>> {
+    let response = client
+        .search(SearchParts::Index(&[WORKS_INDEX]))
+        .body(json!({
+          "aggs": {
+              AGGREGATION_KEY: {
+                "terms": {
+                  "field": group_by.to_keyword_field(),
+                  "min_doc_count": min_works,
+                  "size": limit,
+                  "order": {
+                    "_count": "desc"
+                  },
+                },
+                "aggs": {
+                  AGGREGATION_KEY: {
+                    "significant_terms": {
+                      "field": field.to_keyword_field()
+                    }
+                  }
+                },
+              }
+            },
+          "size": 0,
+        }))
+        .allow_no_indices(true)
+        .send()
+        .await?;
+
+    let response_body = response_json(response).await?;
+    buckets(&response_body)?
+        .iter()
+        .map(|bucket| {
+            Ok(SignificantTags {
+                ship: bucket_key(bucket)?,
+                tags: sub_buckets(bucket)?
+                    .iter()
+                    .map(|bucket| {
+                        Ok(SignificantTag {
+                            tag: bucket_key(bucket)?,
+                            score: bucket
+                                .get("score")
+                                .context("significant term score")?
+                                .as_f64()
+                                .context("significant term score number")?,
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+            })
+        })
+        .collect()
+}
+
+/// Count the distinct values of a tag kind across all works.
+///
+/// Elasticsearch computes this approximately, so large counts may be slightly off.
+pub async fn cardinality(client: &Elasticsearch, field: TagKind) -> Result<u64> {
+    let response = client
+        .search(SearchParts::Index(&[WORKS_INDEX]))
+        .body(json!({
+          "aggs": {
+              AGGREGATION_KEY: {
+                "cardinality": {
+                  "field": field.to_keyword_field()
+                }
+              }
+            },
+          "size": 0,
+        }))
+        .allow_no_indices(true)
+        .send()
+        .await?;
+
+    let response_body = response_json(response).await?;
+    response_body
+        .get("aggregations")
+        .context("Response aggregations key")?
+        .get(AGGREGATION_KEY)
+        .context("Response aggregation key")?
+        .get("value")
+        .context("Response value key")?
+        .as_u64()
+        .context("Response value integer")
+}
+
+/// Load percentiles of word count across all works.
+///
+/// Returns a map of percentile (formatted by Elasticsearch, such as `"50.0"`)
+/// to word count.
+pub async fn word_count_percentiles(
+    client: &Elasticsearch,
+    percentiles: &[f64],
+) -> Result<HashMap<String, f64>> {
+    let response = client
+        .search(SearchParts::Index(&[WORKS_INDEX]))
+        .body(json!({
+          "aggs": {
+              AGGREGATION_KEY: {
+                "percentiles": {
+                  "field": "words",
+                  "percents": percentiles
+                }
+              }
+            },
+          "size": 0,
+        }))
+        .allow_no_indices(true)
+        .send()
+        .await?;
+
+    let response_body = response_json(response).await?;
+    parse_percentiles(
+        response_body
+            .get("aggregations")
+            .context("Response aggregations key")?
+            .get(AGGREGATION_KEY)
+            .context("Response aggregation key")?,
+    )
+}
+
+/// Load the average word count of works with each of the most common tags.
+///
+/// Returns `(tag, average)` pairs, longest average first.
+pub async fn avg_words_per_tag(
+    client: &Elasticsearch,
+    field: TagKind,
+    min_works: usize,
+    limit: usize,
+) -> Result<Vec<(String, f64)>> {
+    avg_metric_per_tag(client, field, &WorkMetric::Words, min_works, limit).await
+}
+
+/// Load the average of `metric` over works with each of the most common tags.
+///
+/// Returns `(tag, average)` pairs, largest average first.
+pub async fn avg_metric_per_tag(
+    client: &Elasticsearch,
+    field: TagKind,
+    metric: &WorkMetric,
+    min_works: usize,
+    limit: usize,
+) -> Result<Vec<(String, f64)>> {
+    metric_per_tag(client, field, "avg", metric.to_field(), min_works, limit).await
+}
+
+/// Load the total of `metric`, such as comments, over works with each of the
+/// most common tags.
+///
+/// Returns `(tag, total)` pairs, largest total first.
+pub async fn sum_metric_per_tag(
+    client: &Elasticsearch,
+    field: TagKind,
+    metric: &WorkMetric,
+    min_works: usize,
+    limit: usize,
+) -> Result<Vec<(String, f64)>> {
+    metric_per_tag(client, field, "sum", metric.to_field(), min_works, limit).await
+}
+
+/// Load a `metric` aggregation, such as `avg`, of a numeric field for each
+/// of the most common tags, largest first.
+async fn metric_per_tag(
+    client: &Elasticsearch,
+    field: TagKind,
+    metric: &str,
+    metric_field: &str,
+    min_works: usize,
+    limit: usize,
+) -> Result<Vec<(String, f64)>> {
+    let mut aggregation = terms_aggregation(&field.to_keyword_field(), min_works, limit);
+    aggregation["aggs"] = json!({
+      AGGREGATION_KEY: {
+        metric: {
+          "field": metric_field
+        }
+      }
+    });
+
+    let response = client
+        .search(SearchParts::Index(&[WORKS_INDEX]))
+        .body(json!({
+          "aggs": {
+            AGGREGATION_KEY: aggregation
+          },
+          "size": 0,
+        }))
+        .allow_no_indices(true)
+        .send()
+        .await?;
+
+    let response_body = response_json(response).await?;
+    parse_metric_per_tag(&response_body)
+}
+
+fn parse_metric_per_tag(response_body: &Value) -> Result<Vec<(String, f64)>> {
+    let mut values = buckets(response_body)?
+        .iter()
+        .map(|bucket| {
+            Ok((
+                bucket_key(bucket)?,
+                bucket
+                    .get(AGGREGATION_KEY)
+                    .and_then(|metric| metric.get("value"))
+                    .context("bucket metric value")?
+                    .as_f64()
+                    .context("bucket metric value number")?,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    values.sort_by(|(_, a), (_, b)| b.partial_cmp(a).expect("metric value to be a number"));
+    Ok(values)
+}
+
+/// Load percentiles of word count for the works of each ship.
+pub async fn ship_word_count_percentiles(
+    client: &Elasticsearch,
+    min_works: usize,
+    limit: usize,
+    percentiles: &[f64],
+) -> Result<Vec<TagPercentiles>> {
+    let response = client
+        .search(SearchParts::Index(&[WORKS_INDEX]))
+        .body(json!({
+          "aggs": {
+              AGGREGATION_KEY: {
+                "terms": {
+                  "field": TagKind::Relationship.to_keyword_field(),
+                  "min_doc_count": min_works,
+                  "size": limit,
+                  "order": {
+                    "_count": "desc"
+                  },
+                },
+                "aggs": {
+                  AGGREGATION_KEY: {
+                    "percentiles": {
+                      "field": "words",
+                      "percents": percentiles
+                    }
+                  }
+                },
+              }
+            },
+          "size": 0,
+        }))
+        .allow_no_indices(true)
+        .send()
+        .await?;
+
+    let response_body = response_json(response).await?;
+    buckets(&response_body)?
+        .iter()
+        .map(|bucket| {
+            Ok(TagPercentiles {
+                tag: bucket_key(bucket)?,
+                percentiles: parse_percentiles(
+                    bucket.get(AGGREGATION_KEY).context("bucket sub agg")?,
+                )?,
+            })
+        })
+        .collect()
+}
+
+/// Parse percentile values, skipping any which are `null` because no works had a value.
+fn parse_percentiles(aggregation: &Value) -> Result<HashMap<String, f64>> {
+    aggregation
+        .get("values")
+        .context("percentiles values key")?
+        .as_object()
+        .context("percentiles values object")?
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(percentile, value)| {
+            Ok((
+                percentile.to_owned(),
+                value.as_f64().context("percentile value number")?,
+            ))
+        })
+        .collect()
+}
+
+/// Load co-occurring pairs of characters from ship tags.
+///
+/// Only relationship tags are supported, as these are split into characters.
+/// Ships can be restricted to a single `ship_kind`, and if `pairs_only` is set
+/// ships without exactly two characters are dropped. Each ship adds its works,
+/// or their total kudos or hits, to its pairs depending on `weight`.
+#[allow(clippy::too_many_arguments)]
+pub async fn co_occurrence(
     client: &Elasticsearch,
+    field: TagKind,
     min_works: usize,
     limit: usize,
+    filter: Option<WorksQuery>,
+    ship_kind: Option<ShipKind>,
+    pairs_only: bool,
+    weight: &CoOccurrenceWeight,
+) -> Result<Vec<CoOccurrence>> {
+    if field != TagKind::Relationship {
+        return Err(Error::UnsupportedTagKind(field));
+    }
+    let mut aggregation = terms_aggregation(&field.to_keyword_field(), min_works, limit);
+    if let Some(ship_kind) = &ship_kind {
+        restrict_to_ship_kind(&mut aggregation, ship_kind);
+    }
+    let frequencies = term_frequencies(client, &[WORKS_INDEX], aggregation, filter, weight).await?;
+    Ok(ship_co_occurrence(
+        frequencies,
+        ship_kind.as_ref(),
+        pairs_only,
+    ))
+}
+
+/// Split ship frequencies into pairs of characters, summing duplicates.
+pub fn ship_co_occurrence(
+    frequencies: Vec<TagFrequency>,
+    ship_kind: Option<&ShipKind>,
+    pairs_only: bool,
+) -> Vec<CoOccurrence> {
+    let mut pairs: HashMap<(String, String), u64> = HashMap::default();
+    for TagFrequency { tag, count } in frequencies {
+        let ship = match Ship::from_str(&tag) {
+            Ok(ship) => ship,
+            Err(error) => {
+                log::warn!("Dropping ship: {}", error);
+                continue;
+            }
+        };
+        if pairs_only && ship.characters.len() != 2 {
+            log::warn!(
+                "Dropping ship: Ship must have exactly two characters: '{:?}'",
+                ship.characters
+            );
+            continue;
+        }
+        if ship_kind.is_some_and(|ship_kind| &ship.kind != ship_kind) {
+            continue;
+        }
+
+        // Characters are already sorted, so each pair is in a consistent order
+        for (index, one) in ship.characters.iter().enumerate() {
+            for two in ship.characters[index + 1..].iter() {
+                // Add rather than assigning here, to allow for duplicate ship tags
+                *pairs.entry((one.clone(), two.clone())).or_default() += count;
+            }
+        }
+    }
+
+    let mut pairs: Vec<CoOccurrence> = pairs
+        .into_iter()
+        .map(|((first, second), count)| CoOccurrence {
+            first,
+            second,
+            count,
+        })
+        .collect();
+    pairs.sort_unstable();
+    pairs
+}
+
+/// Load the co-occurrence of tags within works, such as the characters or
+/// freeforms tagged on each work, rather than splitting ship tags.
+///
+/// Only the `limit` most common tags with at least `min_works` works are
+/// counted, which takes a request to find them before the one counting their pairs.
+/// Each work adds to the edge between every pair of its tags, by one
+/// or by its kudos or hits depending on `weight`.
+pub async fn tag_co_occurrence(
+    client: &Elasticsearch,
     field: TagKind,
-    filter: Option<Value>,
-) -> Result<Vec<(String, u64)>> {
-    let query = filter.unwrap_or(json!({
+    min_works: usize,
+    limit: usize,
+    filter: Option<WorksQuery>,
+    weight: &CoOccurrenceWeight,
+) -> Result<Vec<CoOccurrence>> {
+    // Find the top tags first, so the pairs under each tag can be restricted to them.
+    // Otherwise common tags outside the top list could crowd pairs out of a bucket
+    let tags: Vec<String> = term_frequencies(
+        client,
+        &[WORKS_INDEX],
+        terms_aggregation(&field.to_keyword_field(), min_works, limit),
+        filter.clone(),
+        &CoOccurrenceWeight::Works,
+    )
+    .await?
+    .into_iter()
+    .map(|frequency| frequency.tag)
+    .collect();
+    if tags.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let query = filter.map(|filter| filter.to_query()).unwrap_or(json!({
       "match_all": {}
     }));
+    let mut pair_aggregation = json!({
+      "terms": {
+        "field": field.to_keyword_field(),
+        "include": &tags,
+        "size": tags.len(),
+      }
+    });
+    add_weight_sum(&mut pair_aggregation, weight);
+    let mut aggregation = terms_aggregation(&field.to_keyword_field(), min_works, limit);
+    aggregation["aggs"] = json!({ AGGREGATION_KEY: pair_aggregation });
 
     let response = client
         .search(SearchParts::Index(&[WORKS_INDEX]))
         .body(json!({
           "aggs": {
-              AGGREGATION_KEY: {
-                "terms": {
-                  "field": field.to_keyword_field(),
-                  "min_doc_count": min_works,
-                  "size": limit,
-                  "order": {
-                    "_count": "desc"
-                  },
-                }
-              }
-            },
+            AGGREGATION_KEY: aggregation
+          },
           "size": 0,
           "query": query
         }))
@@ -43,132 +1072,180 @@ pub async fn ship_frequencies(
         .send()
         .await?;
 
-    let response_body = response.json::<Value>().await?;
-    let buckets = response_body
-        .get("aggregations")
-        .context("Response aggregations key")?
-        .get(AGGREGATION_KEY)
-        .context("Response aggregation key")?
-        .get("buckets")
-        .context("Response buckets key")?
-        .as_array()
-        .context("Response buckets array")?;
-    Ok(buckets
-        .into_iter()
-        .map(|bucket| {
-            Ok((
-                bucket
-                    .get("key")
-                    .context("bucket key")?
-                    .as_str()
-                    .context("bucket key string")?
-                    .to_owned(),
-                bucket
-                    .get("doc_count")
-                    .context("bucket doc count")?
-                    .as_u64()
-                    .context("bucket doc count integer")?,
-            ))
-        })
-        .collect::<Result<_>>()?)
+    let response_body = response_json(response).await?;
+    parse_tag_co_occurrence(&response_body, weight)
 }
 
-/// Load the frequencies of ship tags from all works.
-///
-/// Returns a list of `(ship name, count)` pairs.
-pub async fn significant_tags(
+fn parse_tag_co_occurrence(
+    response_body: &Value,
+    weight: &CoOccurrenceWeight,
+) -> Result<Vec<CoOccurrence>> {
+    let buckets = buckets(response_body)?;
+    let tags = buckets
+        .iter()
+        .map(bucket_key)
+        .collect::<Result<HashSet<String>>>()?;
+
+    let mut pairs: Vec<CoOccurrence> = Vec::default();
+    for bucket in buckets.iter() {
+        let first = bucket_key(bucket)?;
+        for bucket in sub_buckets(bucket)?.iter() {
+            let second = bucket_key(bucket)?;
+            // Each pair appears under both tags, and each tag appears with
+            // itself, so only keep one ordering of distinct tags which are
+            // both in the top list.
+            if first >= second || !tags.contains(&second) {
+                continue;
+            }
+            pairs.push(CoOccurrence {
+                first: first.clone(),
+                second,
+                count: bucket_weight(bucket, weight)?,
+            });
+        }
+    }
+    pairs.sort_unstable();
+    Ok(pairs)
+}
+
+/// Load timeseries points of counts of works over time, for the most common ships.
+pub async fn ship_histogram(
     client: &Elasticsearch,
-    min_works: usize,
     limit: usize,
-    field: TagKind,
-) -> Result<Vec<(String, Vec<String>)>> {
+    interval: &Interval,
+) -> Result<Vec<TagHistogram>> {
     let response = client
         .search(SearchParts::Index(&[WORKS_INDEX]))
         .body(json!({
           "aggs": {
-              AGGREGATION_KEY: {
-                "terms": {
-                  "field": TagKind::Relationship.to_keyword_field(),
-                  "min_doc_count": min_works,
-                  "size": limit,
-                  "order": {
-                    "_count": "desc"
-                  },
+            AGGREGATION_KEY: {
+              "terms": {
+                "field": TagKind::Relationship.to_keyword_field(),
+                "order": {
+                  "_count": "desc"
                 },
-                "aggs": {
-                  AGGREGATION_KEY: {
-                    "significant_terms": {
-                      "field": field.to_keyword_field()
-                    }
+                "size": limit,
+              },
+              "aggs": {
+                AGGREGATION_KEY: {
+                  "date_histogram": {
+                    "field": "date",
+                    "calendar_interval": interval.to_calendar_interval(),
+                    "min_doc_count": 0
                   }
-                },
+                }
               }
-            },
+            }
+          },
           "size": 0,
+          "docvalue_fields": [
+            {
+              "field": "date",
+              "format": "date_time"
+            }
+          ],
+          "query": {
+              "match_all": {}
+          }
         }))
         .allow_no_indices(true)
         .send()
         .await?;
 
-    let response_body = response.json::<Value>().await?;
-    let buckets = response_body
-        .get("aggregations")
-        .context("Response aggregations key")?
-        .get(AGGREGATION_KEY)
-        .context("Response aggregation key")?
-        .get("buckets")
-        .context("Response buckets key")?
-        .as_array()
-        .context("Response buckets array")?;
-    Ok(buckets
-        .into_iter()
+    let response_body = response_json(response).await?;
+    parse_ship_histogram(&response_body)
+}
+
+fn parse_ship_histogram(response_body: &Value) -> Result<Vec<TagHistogram>> {
+    buckets(response_body)?
+        .iter()
         .map(|bucket| {
-            Ok((
-                bucket
-                    .get("key")
-                    .context("bucket key")?
-                    .as_str()
-                    .context("bucket key string")?
-                    .to_owned(),
-                bucket
-                    .get(AGGREGATION_KEY)
-                    .context("bucket sub agg")?
-                    .get("buckets")
-                    .context("sub agg buckets key")?
-                    .as_array()
-                    .context("sub agg buckets array")?
-                    .into_iter()
+            Ok(TagHistogram {
+                tag: bucket_key(bucket)?,
+                counts: sub_buckets(bucket)?
+                    .iter()
                     .map(|bucket| {
-                        Ok(bucket
+                        let millis = bucket
                             .get("key")
-                            .context("significant term key")?
-                            .as_str()
-                            .context("bucket key string")?
-                            .to_owned())
+                            .context("sub key")?
+                            .as_i64()
+                            .context("sub key as int")?;
+                        Ok((
+                            DateTime::from_timestamp(millis / 1000, 0)
+                                .context("sub key as date")?
+                                .date_naive(),
+                            bucket_count(bucket)?,
+                        ))
                     })
                     .collect::<Result<_>>()?,
-            ))
+            })
         })
-        .collect::<Result<_>>()?)
+        .collect()
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize)]
-#[serde(rename_all = "lowercase")]
-pub enum ShipKind {
-    Romantic,
-    Platonic,
+/// Decode the body of a search response, failing with the body if the request
+/// was not successful.
+async fn response_json(response: Response) -> Result<Value> {
+    let status_code = response.status_code();
+    if !status_code.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::ElasticsearchResponse {
+            status: status_code.as_u16(),
+            body,
+        });
+    }
+    Ok(response.json::<Value>().await?)
 }
 
-impl FromStr for ShipKind {
-    type Err = Error;
+/// Get the buckets of the top level aggregation in a search response.
+fn buckets(response_body: &Value) -> Result<&[Value]> {
+    sub_buckets(
+        response_body
+            .get("aggregations")
+            .context("Response aggregations key")?,
+    )
+}
 
-    fn from_str(string: &str) -> Result<Self> {
-        match string {
-            "romantic" => Ok(Self::Romantic),
-            "platonic" => Ok(Self::Platonic),
-            _ => Err(anyhow!("Invalid ship kind: '{}'", string)),
-        }
+/// Get the buckets of the aggregation nested in a bucket (or aggregations object).
+fn sub_buckets(bucket: &Value) -> Result<&[Value]> {
+    bucket
+        .get(AGGREGATION_KEY)
+        .context("aggregation key")?
+        .get("buckets")
+        .context("buckets key")?
+        .as_array()
+        .map(Vec::as_slice)
+        .context("buckets array")
+}
+
+fn bucket_key(bucket: &Value) -> Result<String> {
+    Ok(bucket
+        .get("key")
+        .context("bucket key")?
+        .as_str()
+        .context("bucket key string")?
+        .to_owned())
+}
+
+fn bucket_count(bucket: &Value) -> Result<u64> {
+    bucket
+        .get("doc_count")
+        .context("bucket doc count")?
+        .as_u64()
+        .context("bucket doc count integer")
+}
+
+/// Get the works in a bucket, or the sum added by `add_weight_sum`.
+fn bucket_weight(bucket: &Value, weight: &CoOccurrenceWeight) -> Result<u64> {
+    if weight.to_field().is_none() {
+        return bucket_count(bucket);
     }
+    Ok(bucket
+        .get(AGGREGATION_KEY)
+        .and_then(|sum| sum.get("value"))
+        .context("bucket weight sum")?
+        .as_f64()
+        .context("bucket weight sum number")? as u64)
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -186,7 +1263,7 @@ impl FromStr for TagKind {
             "relationship" => Ok(Self::Relationship),
             "character" => Ok(Self::Character),
             "freeform" => Ok(Self::Freeform),
-            _ => Err(anyhow!("Invalid tag kind: '{}'", string)),
+            _ => Err(Error::invalid_value("tag kind", string)),
         }
     }
 }
@@ -204,3 +1281,703 @@ impl TagKind {
         format!("{}.keyword", self.to_field())
     }
 }
+
+/// A numeric field of each work, which can be summed or averaged per tag.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum WorkMetric {
+    Words,
+    Kudos,
+    Hits,
+    Comments,
+    Bookmarks,
+}
+
+impl FromStr for WorkMetric {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "words" => Ok(Self::Words),
+            "kudos" => Ok(Self::Kudos),
+            "hits" => Ok(Self::Hits),
+            "comments" => Ok(Self::Comments),
+            "bookmarks" => Ok(Self::Bookmarks),
+            _ => Err(Error::invalid_value("work metric", string)),
+        }
+    }
+}
+
+impl WorkMetric {
+    pub fn to_field(&self) -> &'static str {
+        match self {
+            Self::Words => "words",
+            Self::Kudos => "kudos",
+            Self::Hits => "hits",
+            Self::Comments => "comments",
+            Self::Bookmarks => "bookmarks",
+        }
+    }
+}
+
+/// How much each work adds to the co-occurrence of its tags.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CoOccurrenceWeight {
+    /// Each work counts once.
+    Works,
+    /// Each work counts by its number of kudos.
+    Kudos,
+    /// Each work counts by its number of hits.
+    Hits,
+}
+
+impl CoOccurrenceWeight {
+    /// Field summed for each work, or `None` if each work counts once.
+    pub fn to_field(&self) -> Option<&'static str> {
+        match self {
+            Self::Works => None,
+            Self::Kudos => Some("kudos"),
+            Self::Hits => Some("hits"),
+        }
+    }
+}
+
+impl FromStr for CoOccurrenceWeight {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "works" => Ok(Self::Works),
+            "kudos" => Ok(Self::Kudos),
+            "hits" => Ok(Self::Hits),
+            _ => Err(Error::invalid_value("co-occurrence weight", string)),
+        }
+    }
+}
+
+/// Width of each bucket in a date histogram.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl FromStr for Interval {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "day" => Ok(Self::Day),
+            "week" => Ok(Self::Week),
+            "month" => Ok(Self::Month),
+            "year" => Ok(Self::Year),
+            _ => Err(Error::invalid_value("interval", string)),
+        }
+    }
+}
+
+impl Interval {
+    pub fn to_calendar_interval(&self) -> &'static str {
+        match self {
+            Self::Day => "1d",
+            Self::Week => "1w",
+            Self::Month => "1M",
+            Self::Year => "1y",
+        }
+    }
+
+    pub fn to_adjective(&self) -> &'static str {
+        match self {
+            Self::Day => "Daily",
+            Self::Week => "Weekly",
+            Self::Month => "Monthly",
+            Self::Year => "Yearly",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_ship_co_occurrence() {
+        assert_eq!(
+            ship_co_occurrence(
+                vec![
+                    TagFrequency {
+                        tag: "Katara/Zuko (Avatar)".to_owned(),
+                        count: 100
+                    },
+                    TagFrequency {
+                        tag: "Zuko/Katara".to_owned(),
+                        count: 10
+                    },
+                    TagFrequency {
+                        tag: "Aang & Sokka".to_owned(),
+                        count: 50
+                    },
+                    TagFrequency {
+                        tag: "Sokka/Suki/Zuko".to_owned(),
+                        count: 20
+                    },
+                    TagFrequency {
+                        tag: "Minor or Background Relationship(s)".to_owned(),
+                        count: 1000
+                    },
+                ],
+                None,
+                false
+            ),
+            vec![
+                CoOccurrence {
+                    first: "Aang".to_owned(),
+                    second: "Sokka".to_owned(),
+                    count: 50
+                },
+                CoOccurrence {
+                    first: "Katara".to_owned(),
+                    second: "Zuko".to_owned(),
+                    count: 110
+                },
+                CoOccurrence {
+                    first: "Sokka".to_owned(),
+                    second: "Suki".to_owned(),
+                    count: 20
+                },
+                CoOccurrence {
+                    first: "Sokka".to_owned(),
+                    second: "Zuko".to_owned(),
+                    count: 20
+                },
+                CoOccurrence {
+                    first: "Suki".to_owned(),
+                    second: "Zuko".to_owned(),
+                    count: 20
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ship_co_occurrence_pairs_of_kind() {
+        assert_eq!(
+            ship_co_occurrence(
+                vec![
+                    TagFrequency {
+                        tag: "Katara/Zuko (Avatar)".to_owned(),
+                        count: 100
+                    },
+                    TagFrequency {
+                        tag: "Aang & Sokka".to_owned(),
+                        count: 50
+                    },
+                    TagFrequency {
+                        tag: "Sokka/Suki/Zuko".to_owned(),
+                        count: 20
+                    },
+                ],
+                Some(&ShipKind::Romantic),
+                true
+            ),
+            vec![CoOccurrence {
+                first: "Katara".to_owned(),
+                second: "Zuko".to_owned(),
+                count: 100
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_tag_co_occurrence() {
+        let response_body = json!({
+          "aggregations": {
+            AGGREGATION_KEY: {
+              "buckets": [
+                {
+                  "key": "Zuko",
+                  "doc_count": 10,
+                  AGGREGATION_KEY: {
+                    "buckets": [
+                      { "key": "Zuko", "doc_count": 10, AGGREGATION_KEY: { "value": 500.0 } },
+                      { "key": "Katara", "doc_count": 6, AGGREGATION_KEY: { "value": 300.0 } },
+                      { "key": "Iroh", "doc_count": 4, AGGREGATION_KEY: { "value": 80.0 } }
+                    ]
+                  }
+                },
+                {
+                  "key": "Katara",
+                  "doc_count": 8,
+                  AGGREGATION_KEY: {
+                    "buckets": [
+                      { "key": "Katara", "doc_count": 8, AGGREGATION_KEY: { "value": 400.0 } },
+                      { "key": "Zuko", "doc_count": 6, AGGREGATION_KEY: { "value": 300.0 } }
+                    ]
+                  }
+                }
+              ]
+            }
+          }
+        });
+        assert_eq!(
+            parse_tag_co_occurrence(&response_body, &CoOccurrenceWeight::Works).unwrap(),
+            vec![CoOccurrence {
+                first: "Katara".to_owned(),
+                second: "Zuko".to_owned(),
+                count: 6
+            }]
+        );
+        assert_eq!(
+            parse_tag_co_occurrence(&response_body, &CoOccurrenceWeight::Kudos).unwrap(),
+            vec![CoOccurrence {
+                first: "Katara".to_owned(),
+                second: "Zuko".to_owned(),
+                count: 300
+            }]
+        );
+    }
+
+    #[test]
+    fn test_restrict_to_ship_kind() {
+        let mut aggregation = terms_aggregation("relationships.keyword", 5, 10);
+        restrict_to_ship_kind(&mut aggregation, &ShipKind::Platonic);
+        assert_eq!(
+            aggregation,
+            json!({
+              "terms": {
+                "field": "relationships.keyword",
+                "min_doc_count": 5,
+                "size": 10,
+                "order": {
+                  "_count": "desc"
+                },
+                "include": ".*\\&.*",
+                "exclude": ".*/.*"
+              }
+            })
+        );
+
+        let mut aggregation = terms_aggregation("relationships.keyword", 5, 10);
+        restrict_to_ship_kind(&mut aggregation, &ShipKind::Romantic);
+        assert_eq!(aggregation["terms"]["include"], ".*/.*");
+        assert!(aggregation["terms"].get("exclude").is_none());
+    }
+
+    #[test]
+    fn test_bulk_errors() {
+        assert!(bulk_errors(&json!({
+          "took": 3,
+          "errors": false,
+          "items": [{ "index": { "_id": "1", "status": 201 } }]
+        }))
+        .is_ok());
+
+        let error = bulk_errors(&json!({
+          "took": 3,
+          "errors": true,
+          "items": [
+            { "index": { "_id": "1", "status": 201 } },
+            {
+              "index": {
+                "_id": "2",
+                "status": 400,
+                "error": {
+                  "type": "mapper_parsing_exception",
+                  "reason": "failed to parse field [date] of type [date]"
+                }
+              }
+            }
+          ]
+        }))
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Failed to index 1 works, such as 2: failed to parse field [date] of type [date]"
+        );
+    }
+
+    #[test]
+    fn test_mapping_works_fields() {
+        let properties = &MAPPING_WORKS["properties"];
+        // Fields aggregated and filtered on by their keyword subfield
+        let keyword_fields = [
+            TagKind::Relationship.to_keyword_field(),
+            TagKind::Character.to_keyword_field(),
+            TagKind::Freeform.to_keyword_field(),
+            "authors.keyword".to_owned(),
+            "language.keyword".to_owned(),
+        ];
+        for field in keyword_fields.iter() {
+            let (name, subfield) = field.split_once('.').unwrap();
+            assert_eq!(
+                properties[name]["fields"][subfield]["type"], "keyword",
+                "{}",
+                field
+            );
+        }
+        for metric in ["words", "kudos", "hits", "comments", "bookmarks"].iter() {
+            let field = metric.parse::<WorkMetric>().unwrap().to_field();
+            assert_eq!(properties[field]["type"], "long", "{}", field);
+        }
+
+        // Every field indexed is mapped, rather than left to dynamic mapping
+        let works: Vec<Work> = serde_json::from_str(include_str!("search.json")).unwrap();
+        let indexed =
+            serde_json::to_value(IndexedWork::from(works.into_iter().next().unwrap())).unwrap();
+        for field in indexed.as_object().unwrap().keys() {
+            assert!(properties.get(field).is_some(), "{} is not mapped", field);
+        }
+    }
+
+    #[test]
+    fn test_indexed_work() {
+        let works: Vec<Work> = serde_json::from_str(include_str!("search.json")).unwrap();
+        let work = works.into_iter().next().unwrap();
+        let indexed = serde_json::to_value(IndexedWork::from(work)).unwrap();
+        assert_eq!(indexed["id"], "27871338");
+        assert_eq!(indexed["kudos_hits_ratio"], 18. / 83.);
+        assert_eq!(indexed["words_per_chapter"], 1784.);
+        assert_eq!(indexed["language"], "English");
+        assert_eq!(indexed["language_code"], "en");
+    }
+
+    #[test]
+    fn test_parse_hits() {
+        let works: Vec<Work> = serde_json::from_str(include_str!("search.json")).unwrap();
+        let response_body = json!({
+          "hits": {
+            "total": { "value": 20, "relation": "eq" },
+            "hits": [
+              {
+                "_index": "works",
+                "_id": "27871338",
+                "_source": {
+                  "id": "27871338",
+                  "title": "Risen from the Ashes",
+                  "authors": ["PixelEnchanter"],
+                  "relationships": [],
+                  "characters": ["Zuko (Avatar)", "Sozin (Avatar)", "Azulon (Avatar)"],
+                  "freeforms": [
+                    "Avatar Zuko (Avatar)",
+                    "Alternate Universe - Canon Divergence",
+                    "Other Additional Tags to Be Added"
+                  ],
+                  "date": "2020-12-03",
+                  "language": "English",
+                  "words": 1784,
+                  "chapters": 1,
+                  "kudos": 18,
+                  "hits": 83,
+                  "comments": 2,
+                  "bookmarks": 2,
+                  "kudos_hits_ratio": 0.2169
+                },
+                "sort": [18]
+              }
+            ]
+          }
+        });
+        assert_eq!(
+            parse_hits(&response_body).unwrap(),
+            works.into_iter().take(1).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_composite_page() {
+        let response_body = json!({
+          "aggregations": {
+            "aggregation_key": {
+              "after_key": { "tag": "Katara/Zuko" },
+              "buckets": [
+                { "key": { "tag": "Aang/Katara" }, "doc_count": 2 },
+                { "key": { "tag": "Katara/Zuko" }, "doc_count": 4 }
+              ]
+            }
+          }
+        });
+        assert_eq!(
+            parse_composite_page(&response_body).unwrap(),
+            (
+                vec![
+                    TagFrequency {
+                        tag: "Aang/Katara".to_owned(),
+                        count: 2,
+                    },
+                    TagFrequency {
+                        tag: "Katara/Zuko".to_owned(),
+                        count: 4,
+                    },
+                ],
+                Some(json!({ "tag": "Katara/Zuko" }))
+            )
+        );
+
+        let last_page = json!({
+          "aggregations": {
+            "aggregation_key": {
+              "after_key": { "tag": "Katara/Zuko" },
+              "buckets": []
+            }
+          }
+        });
+        assert_eq!(parse_composite_page(&last_page).unwrap(), (vec![], None));
+    }
+
+    #[test]
+    fn test_composite_aggregation() {
+        assert_eq!(
+            composite_aggregation("relationships.keyword", 100, Some(json!({ "tag": "A/B" }))),
+            json!({
+              "composite": {
+                "size": 100,
+                "sources": [
+                  { "tag": { "terms": { "field": "relationships.keyword" } } }
+                ],
+                "after": { "tag": "A/B" }
+              }
+            })
+        );
+    }
+
+    #[test]
+    fn test_work_metric_from_str() {
+        assert_eq!(
+            "comments".parse::<WorkMetric>().unwrap(),
+            WorkMetric::Comments
+        );
+        assert_eq!(
+            "bookmarks".parse::<WorkMetric>().unwrap().to_field(),
+            "bookmarks"
+        );
+        assert!("chapters".parse::<WorkMetric>().is_err());
+    }
+
+    #[test]
+    fn test_parse_metric_per_tag() {
+        let response_body = json!({
+          "aggregations": {
+            "aggregation_key": {
+              "doc_count_error_upper_bound": 0,
+              "sum_other_doc_count": 0,
+              "buckets": [
+                {
+                  "key": "Katara/Zuko",
+                  "doc_count": 4,
+                  "aggregation_key": { "value": 2500.5 }
+                },
+                {
+                  "key": "Aang/Katara",
+                  "doc_count": 2,
+                  "aggregation_key": { "value": 7000.0 }
+                }
+              ]
+            }
+          }
+        });
+        assert_eq!(
+            parse_metric_per_tag(&response_body).unwrap(),
+            vec![
+                ("Aang/Katara".to_owned(), 7000.),
+                ("Katara/Zuko".to_owned(), 2500.5)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_weight_sum() {
+        let mut aggregation = terms_aggregation("relationships.keyword", 5, 10);
+        add_weight_sum(&mut aggregation, &CoOccurrenceWeight::Works);
+        assert!(aggregation.get("aggs").is_none());
+
+        add_weight_sum(&mut aggregation, &CoOccurrenceWeight::Hits);
+        assert_eq!(
+            aggregation["aggs"],
+            json!({
+              "aggregation_key": {
+                "sum": {
+                  "field": "hits"
+                }
+              }
+            })
+        );
+        assert_eq!(
+            bucket_weight(
+                &json!({ "key": "Katara/Zuko", "doc_count": 2, "aggregation_key": { "value": 350.0 } }),
+                &CoOccurrenceWeight::Hits
+            )
+            .unwrap(),
+            350
+        );
+    }
+
+    #[test]
+    fn test_parse_percentiles() {
+        let percentiles = parse_percentiles(&json!({
+          "values": { "50.0": 1200.0, "95.0": null }
+        }))
+        .unwrap();
+        assert_eq!(percentiles.len(), 1);
+        assert_eq!(percentiles["50.0"], 1200.0);
+        assert!(parse_percentiles(&json!({ "values": { "50.0": "many" } })).is_err());
+    }
+
+    #[test]
+    fn test_parse_existing_ids() {
+        assert_eq!(
+            parse_existing_ids(&json!({
+              "docs": [
+                { "_index": "works", "_id": "1", "_version": 1, "found": true },
+                { "_index": "works", "_id": "2", "found": false }
+              ]
+            }))
+            .unwrap(),
+            vec!["1".to_owned()].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_parse_ship_histogram() {
+        let response_body = json!({
+          "took": 3,
+          "timed_out": false,
+          "hits": {
+            "total": {
+              "value": 3,
+              "relation": "eq"
+            },
+            "max_score": null,
+            "hits": []
+          },
+          "aggregations": {
+            AGGREGATION_KEY: {
+              "doc_count_error_upper_bound": 0,
+              "sum_other_doc_count": 0,
+              "buckets": [
+                {
+                  "key": "Katara/Zuko (Avatar)",
+                  "doc_count": 3,
+                  AGGREGATION_KEY: {
+                    "buckets": [
+                      {
+                        "key_as_string": "2020-01-01T00:00:00.000Z",
+                        "key": 1577836800000u64,
+                        "doc_count": 2
+                      },
+                      {
+                        "key_as_string": "2020-02-01T00:00:00.000Z",
+                        "key": 1580515200000u64,
+                        "doc_count": 1
+                      }
+                    ]
+                  }
+                }
+              ]
+            }
+          }
+        });
+        assert_eq!(
+            parse_ship_histogram(&response_body).unwrap(),
+            vec![TagHistogram {
+                tag: "Katara/Zuko (Avatar)".to_owned(),
+                counts: vec![
+                    (NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(), 2),
+                    (NaiveDate::from_ymd_opt(2020, 2, 1).unwrap(), 1),
+                ]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_ship_histogram_missing_aggregations() {
+        assert!(parse_ship_histogram(&json!({})).is_err());
+    }
+
+    #[test]
+    fn test_works_query() {
+        assert_eq!(
+            WorksQuery::default()
+                .min_kudos(100)
+                .language("English")
+                .date_range(Some(NaiveDate::from_ymd_opt(2020, 1, 1).unwrap()), None)
+                .relationship("Katara/Zuko (Avatar)")
+                .to_query(),
+            json!({
+              "bool": {
+                "filter": [
+                  {
+                    "range": {
+                      "kudos": {
+                        "gte": 100
+                      }
+                    }
+                  },
+                  {
+                    "term": {
+                      "language.keyword": "English"
+                    }
+                  },
+                  {
+                    "range": {
+                      "date": {
+                        "gte": "2020-01-01"
+                      }
+                    }
+                  },
+                  {
+                    "term": {
+                      "relationships.keyword": "Katara/Zuko (Avatar)"
+                    }
+                  }
+                ]
+              }
+            })
+        );
+    }
+
+    #[test]
+    fn test_works_query_default() {
+        assert_eq!(
+            WorksQuery::default().date_range(None, None).to_query(),
+            json!({
+              "bool": {
+                "filter": []
+              }
+            })
+        );
+    }
+
+    #[test]
+    fn test_date_range() {
+        assert_eq!(
+            date_range(
+                Some(NaiveDate::from_ymd_opt(2020, 1, 1).unwrap()),
+                Some(NaiveDate::from_ymd_opt(2020, 12, 31).unwrap())
+            ),
+            json!({
+              "range": {
+                "date": {
+                  "gte": "2020-01-01",
+                  "lte": "2020-12-31"
+                }
+              }
+            })
+        );
+    }
+
+    #[test]
+    fn test_date_range_open_ended() {
+        assert_eq!(
+            date_range(Some(NaiveDate::from_ymd_opt(2020, 1, 1).unwrap()), None),
+            json!({
+              "range": {
+                "date": {
+                  "gte": "2020-01-01"
+                }
+              }
+            })
+        );
+    }
+}
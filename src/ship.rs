@@ -0,0 +1,140 @@
+use crate::error::{Error, Result};
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShipKind {
+    Romantic,
+    Platonic,
+}
+
+impl FromStr for ShipKind {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "romantic" => Ok(Self::Romantic),
+            "platonic" => Ok(Self::Platonic),
+            _ => Err(Error::invalid_value("ship kind", string)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize)]
+pub struct Ship {
+    pub characters: Vec<String>,
+    pub kind: ShipKind,
+}
+
+impl FromStr for Ship {
+    type Err = Error;
+
+    /// Given a ship tag, returns the characters in the ship.
+    ///
+    /// Any fandom disambiguation such as `(Avatar)` is stripped from each
+    /// character, and the characters are sorted, to make tag deduplication easier.
+    ///
+    /// A tag with both delimiters, such as `Zuko/Katara & Aang`, is romantic, and
+    /// lists every character either side of each delimiter.
+    ///
+    /// This function will return an error if the ship kind could not be determined.
+    fn from_str(ship: &str) -> Result<Self> {
+        let kind = if ship.contains('/') {
+            ShipKind::Romantic
+        } else if ship.contains('&') {
+            ShipKind::Platonic
+        } else {
+            return Err(Error::UnknownShipKind(ship.to_owned()));
+        };
+
+        // Split on separators to get characters
+        let mut characters: Vec<String> = ship
+            .split(['/', '&'])
+            .map(|mut name| {
+                if let Some(fandom_start) = name.find('(') {
+                    name = &name[..fandom_start];
+                }
+                name.trim().to_owned()
+            })
+            .collect();
+        characters.sort_unstable();
+
+        Ok(Self { characters, kind })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_ship_from_str() {
+        assert_eq!(
+            Ship::from_str("Zuko/Katara (Avatar)").unwrap(),
+            Ship {
+                characters: vec!["Katara".to_owned(), "Zuko".to_owned()],
+                kind: ShipKind::Romantic,
+            }
+        );
+        assert_eq!(
+            Ship::from_str("Sokka & Aang").unwrap(),
+            Ship {
+                characters: vec!["Aang".to_owned(), "Sokka".to_owned()],
+                kind: ShipKind::Platonic,
+            }
+        );
+    }
+
+    #[test]
+    fn test_ship_from_str_unknown_kind() {
+        assert!(Ship::from_str("Minor or Background Relationship(s)").is_err());
+        assert!(Ship::from_str("Zuko (Avatar)").is_err());
+    }
+
+    #[test]
+    fn test_ship_from_str_poly() {
+        assert_eq!(
+            Ship::from_str("Zuko/Sokka/Suki (Avatar)").unwrap(),
+            Ship {
+                characters: vec!["Sokka".to_owned(), "Suki".to_owned(), "Zuko".to_owned()],
+                kind: ShipKind::Romantic,
+            }
+        );
+        assert_eq!(
+            Ship::from_str("Aang & Katara & Sokka & Toph").unwrap().kind,
+            ShipKind::Platonic
+        );
+    }
+
+    #[test]
+    fn test_ship_from_str_fandom_on_each_character() {
+        assert_eq!(
+            Ship::from_str("Zuko (Avatar) / Katara (Avatar)")
+                .unwrap()
+                .characters,
+            vec!["Katara".to_owned(), "Zuko".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_ship_from_str_whitespace() {
+        assert_eq!(
+            Ship::from_str("  Zuko /Katara  ").unwrap().characters,
+            vec!["Katara".to_owned(), "Zuko".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_ship_from_str_mixed_delimiters() {
+        // Romantic takes precedence, and every character is split out
+        assert_eq!(
+            Ship::from_str("Zuko/Katara & Aang").unwrap(),
+            Ship {
+                characters: vec!["Aang".to_owned(), "Katara".to_owned(), "Zuko".to_owned()],
+                kind: ShipKind::Romantic,
+            }
+        );
+    }
+}
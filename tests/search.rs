@@ -0,0 +1,300 @@
+use elasticsearch::{http::transport::Transport, Elasticsearch};
+use fandom_data::{
+    scrape::Work,
+    search::{
+        existing_work_ids, index_works, put_works_mapping, ship_frequencies, significant_tags,
+        tag_co_occurrence, CoOccurrence, CoOccurrenceWeight, SignificantTag, SignificantTags,
+        TagFrequency, TagKind,
+    },
+    Error,
+};
+use pretty_assertions::assert_eq;
+use serde_json::{json, Value};
+use tokio_compat_02::FutureExt;
+use wiremock::{
+    matchers::{body_string_contains, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+/// Start a server which answers every search of the works index with `status` and `body`.
+async fn mock_search(status: u16, body: Value) -> (MockServer, Elasticsearch) {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/works/_search"))
+        .respond_with(ResponseTemplate::new(status).set_body_json(body))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = Elasticsearch::new(Transport::single_node(&server.uri()).unwrap());
+    (server, client)
+}
+
+#[tokio::test]
+async fn test_ship_frequencies() {
+    let (_server, client) = mock_search(
+        200,
+        json!({
+          "took": 3,
+          "timed_out": false,
+          "hits": { "total": { "value": 120, "relation": "eq" }, "hits": [] },
+          "aggregations": {
+            "aggregation_key": {
+              "doc_count_error_upper_bound": 0,
+              "sum_other_doc_count": 12,
+              "buckets": [
+                { "key": "Katara/Zuko (Avatar)", "doc_count": 100 },
+                { "key": "Aang/Katara (Avatar)", "doc_count": 30 }
+              ]
+            }
+          }
+        }),
+    )
+    .await;
+
+    assert_eq!(
+        ship_frequencies(&client, 1, 10, TagKind::Relationship, None, None)
+            .compat()
+            .await
+            .unwrap(),
+        vec![
+            TagFrequency {
+                tag: "Katara/Zuko (Avatar)".to_owned(),
+                count: 100,
+            },
+            TagFrequency {
+                tag: "Aang/Katara (Avatar)".to_owned(),
+                count: 30,
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_significant_tags() {
+    let (_server, client) = mock_search(
+        200,
+        json!({
+          "aggregations": {
+            "aggregation_key": {
+              "buckets": [
+                {
+                  "key": "Katara/Zuko (Avatar)",
+                  "doc_count": 100,
+                  "aggregation_key": {
+                    "doc_count": 100,
+                    "bg_count": 2000,
+                    "buckets": [
+                      { "key": "Fluff", "doc_count": 40, "score": 0.25, "bg_count": 300 }
+                    ]
+                  }
+                }
+              ]
+            }
+          }
+        }),
+    )
+    .await;
+
+    assert_eq!(
+        significant_tags(&client, 1, 10, TagKind::Relationship, TagKind::Freeform)
+            .compat()
+            .await
+            .unwrap(),
+        vec![SignificantTags {
+            ship: "Katara/Zuko (Avatar)".to_owned(),
+            tags: vec![SignificantTag {
+                tag: "Fluff".to_owned(),
+                score: 0.25,
+            }],
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_ship_frequencies_missing_aggregations() {
+    let (_server, client) = mock_search(200, json!({ "hits": { "hits": [] } })).await;
+
+    let error = ship_frequencies(&client, 1, 10, TagKind::Relationship, None, None)
+        .compat()
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Error::Parse { .. }), "{:?}", error);
+}
+
+#[tokio::test]
+async fn test_significant_tags_error_response() {
+    let (_server, client) = mock_search(
+        400,
+        json!({
+          "error": { "type": "search_phase_execution_exception" },
+          "status": 400
+        }),
+    )
+    .await;
+
+    let error = significant_tags(&client, 1, 10, TagKind::Relationship, TagKind::Freeform)
+        .compat()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, Error::ElasticsearchResponse { status: 400, .. }),
+        "{:?}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_existing_work_ids_missing_index() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/works/_mget"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+          "error": { "type": "index_not_found_exception", "index": "works" },
+          "status": 404
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = Elasticsearch::new(Transport::single_node(&server.uri()).unwrap());
+
+    let ids = existing_work_ids(&client, &["1", "2"])
+        .compat()
+        .await
+        .unwrap();
+    assert!(ids.is_empty());
+}
+
+#[tokio::test]
+async fn test_index_works_request_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/works/_bulk"))
+        .respond_with(ResponseTemplate::new(413))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = Elasticsearch::new(Transport::single_node(&server.uri()).unwrap());
+    let work: Work = serde_json::from_value(json!({
+      "id": "1",
+      "title": "Title",
+      "authors": ["Author"],
+      "relationships": [],
+      "characters": [],
+      "freeforms": [],
+      "date": "2020-11-01",
+      "language": "English",
+      "words": 1000,
+      "kudos": 10,
+      "hits": 100
+    }))
+    .unwrap();
+
+    let error = index_works(&client, vec![work]).compat().await.unwrap_err();
+    assert!(
+        matches!(error, Error::ElasticsearchResponse { status: 413, .. }),
+        "{:?}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_put_works_mapping_existing_index_conflict() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .and(path("/works"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    // An index mapped dynamically has a float ratio, which can't become a scaled float
+    Mock::given(method("PUT"))
+        .and(path("/works/_mapping"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+          "error": {
+            "type": "illegal_argument_exception",
+            "reason": "mapper [kudos_hits_ratio] cannot be changed from type [float] to [scaled_float]"
+          },
+          "status": 400
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = Elasticsearch::new(Transport::single_node(&server.uri()).unwrap());
+
+    put_works_mapping(&client).compat().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tag_co_occurrence_restricted_to_top_tags() {
+    let server = MockServer::start().await;
+    // Pairs are only counted with the top tags, found by the first request
+    Mock::given(method("POST"))
+        .and(path("/works/_search"))
+        .and(body_string_contains(r#""include":["Aang","Katara"]"#))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "aggregations": {
+            "aggregation_key": {
+              "buckets": [
+                {
+                  "key": "Aang",
+                  "doc_count": 20,
+                  "aggregation_key": {
+                    "buckets": [
+                      { "key": "Aang", "doc_count": 20 },
+                      { "key": "Katara", "doc_count": 8 }
+                    ]
+                  }
+                },
+                {
+                  "key": "Katara",
+                  "doc_count": 10,
+                  "aggregation_key": {
+                    "buckets": [
+                      { "key": "Katara", "doc_count": 10 },
+                      { "key": "Aang", "doc_count": 8 }
+                    ]
+                  }
+                }
+              ]
+            }
+          }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/works/_search"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "aggregations": {
+            "aggregation_key": {
+              "buckets": [
+                { "key": "Aang", "doc_count": 20 },
+                { "key": "Katara", "doc_count": 10 }
+              ]
+            }
+          }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = Elasticsearch::new(Transport::single_node(&server.uri()).unwrap());
+
+    assert_eq!(
+        tag_co_occurrence(
+            &client,
+            TagKind::Character,
+            1,
+            2,
+            None,
+            &CoOccurrenceWeight::Works
+        )
+        .compat()
+        .await
+        .unwrap(),
+        vec![CoOccurrence {
+            first: "Aang".to_owned(),
+            second: "Katara".to_owned(),
+            count: 8,
+        }]
+    );
+}